@@ -0,0 +1,130 @@
+// compares the old wall collision check (testing every collider) against the TileGrid lookup
+// on a 5x5 block map built from the files in assets/map_blocks/
+//
+// cargo bench --bench collision
+
+use std::{
+    fs,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use bevy::{prelude::*, sprite::collide_aabb::collide};
+use rand::prelude::*;
+
+#[allow(dead_code)]
+#[path = "../src/tilegrid.rs"]
+mod tilegrid;
+
+use tilegrid::{tile_translation, TileGrid};
+
+const TILE_SIZE: f32 = 25.0;
+const MAP_SIZE: i32 = 2;
+const QUERIES: usize = 20_000;
+
+fn main() {
+    let mut block_files: Vec<String> =
+        fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/map_blocks"))
+            .expect("map_blocks directory not found")
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "txt"))
+            .map(|entry| fs::read_to_string(entry.path()).unwrap())
+            .collect();
+    block_files.sort();
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut wall_tiles = Vec::new();
+    for block_x in -MAP_SIZE..=MAP_SIZE {
+        for block_y in -MAP_SIZE..=MAP_SIZE {
+            let block = block_files.choose(&mut rng).unwrap();
+            for (y, line) in block
+                .lines()
+                .filter(|line| !line.starts_with('!'))
+                .enumerate()
+            {
+                for (x, char) in line.chars().enumerate() {
+                    if char == '#' {
+                        wall_tiles
+                            .push(IVec2::new(block_x * 32 + x as i32, block_y * 32 + y as i32));
+                    }
+                }
+            }
+        }
+    }
+
+    let border_size = ((MAP_SIZE * 2 + 1) * 32) as f32 * TILE_SIZE;
+    let borders = vec![
+        (
+            Vec3::new(0.0, border_size / 2.0 * 0.98, 0.0),
+            Vec2::new(border_size * 0.98, TILE_SIZE),
+        ),
+        (
+            Vec3::new(0.0, -border_size / 2.0 * 0.98, 0.0),
+            Vec2::new(border_size * 0.98, TILE_SIZE),
+        ),
+        (
+            Vec3::new(-border_size / 2.0 * 0.98, 0.0, 0.0),
+            Vec2::new(TILE_SIZE, border_size * 0.98),
+        ),
+        (
+            Vec3::new(border_size / 2.0 * 0.98, 0.0, 0.0),
+            Vec2::new(TILE_SIZE, border_size * 0.98),
+        ),
+    ];
+
+    let mut colliders: Vec<(Vec3, Vec2)> = wall_tiles
+        .iter()
+        .map(|tile| (tile_translation(*tile), Vec2::splat(TILE_SIZE)))
+        .collect();
+    colliders.extend(borders.iter().cloned());
+    let grid = TileGrid::new(&wall_tiles, borders);
+
+    let half_extent = border_size / 2.0;
+    let positions: Vec<Vec3> = (0..QUERIES)
+        .map(|_| {
+            Vec3::new(
+                rng.gen_range(-half_extent..half_extent),
+                rng.gen_range(-half_extent..half_extent),
+                0.0,
+            )
+        })
+        .collect();
+
+    let linear_scan = |position: Vec3| {
+        colliders.iter().any(|(translation, size)| {
+            collide(position, Vec2::splat(TILE_SIZE), *translation, *size).is_some()
+        })
+    };
+    let grid_lookup = |position: Vec3| grid.collides(position, Vec2::splat(TILE_SIZE));
+
+    for position in positions.iter() {
+        assert_eq!(
+            linear_scan(*position),
+            grid_lookup(*position),
+            "collision checks disagree at {}",
+            position
+        );
+    }
+
+    println!("{} colliders, {} queries", colliders.len(), QUERIES);
+    let linear = time(&positions, linear_scan);
+    let grid = time(&positions, grid_lookup);
+    println!("linear scan: {:>10.1} ns/query", per_query(linear));
+    println!("tile grid:   {:>10.1} ns/query", per_query(grid));
+    println!(
+        "speedup:     {:>10.1}x",
+        linear.as_secs_f64() / grid.as_secs_f64()
+    );
+}
+
+fn time(positions: &[Vec3], check: impl Fn(Vec3) -> bool) -> Duration {
+    let start = Instant::now();
+    for position in positions.iter() {
+        black_box(check(black_box(*position)));
+    }
+    start.elapsed()
+}
+
+fn per_query(duration: Duration) -> f64 {
+    duration.as_nanos() as f64 / QUERIES as f64
+}
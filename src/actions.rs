@@ -0,0 +1,434 @@
+use bevy::{
+    input::{gamepad::GamepadButtonType, InputSystem},
+    prelude::*,
+};
+
+use crate::{components::CameraFlag, settings::Settings};
+
+// how far the right stick has to be pushed before it takes over aiming from the mouse
+const AIM_STICK_THRESHOLD: f32 = 0.3;
+
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        // actions are worked out once per frame, straight after bevy reads the devices, so
+        // every system sees the same presses
+        app.init_resource::<Input<Action>>()
+            .init_resource::<ActionAxes>()
+            .add_system_to_stage(CoreStage::PreUpdate, update_actions.after(InputSystem));
+    }
+}
+
+// the buttons the game cares about, read with Input<Action> the same way as Input<KeyCode>
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Fire,
+    Pause,
+    Confirm,
+    Cancel,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Default)]
+pub struct ActionAxes {
+    // each axis goes from -1 to 1 and the whole vector is never longer than 1
+    pub move_axis: Vec2,
+    // None when the cursor is outside the window or the stick and aim keys are let go
+    pub aim: Option<Aim>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Aim {
+    // the cursor, already in world space so it doesn't matter where the camera is
+    Point(Vec2),
+    // a unit vector from the right stick or the arrow keys
+    Direction(Vec2),
+}
+
+#[derive(Default, PartialEq, Eq)]
+enum AimSource {
+    #[default]
+    Mouse,
+    Gamepad,
+    Keys,
+}
+
+// Up, Down, Left and Right come from the movement bindings, the arrow keys are only for aiming
+const KEYBOARD_ACTIONS: [(Action, KeyCode); 2] = [
+    (Action::Confirm, KeyCode::Return),
+    (Action::Cancel, KeyCode::Escape),
+];
+
+// the arrow keys aim for people playing without a mouse, like a second stick
+const AIM_KEYS: [(KeyCode, Vec2); 4] = [
+    (KeyCode::Up, Vec2::Y),
+    (KeyCode::Down, Vec2::NEG_Y),
+    (KeyCode::Left, Vec2::NEG_X),
+    (KeyCode::Right, Vec2::X),
+];
+
+const GAMEPAD_ACTIONS: [(Action, GamepadButtonType); 9] = [
+    (Action::Fire, GamepadButtonType::RightTrigger2),
+    (Action::Fire, GamepadButtonType::RightTrigger),
+    (Action::Pause, GamepadButtonType::Start),
+    (Action::Confirm, GamepadButtonType::South),
+    (Action::Cancel, GamepadButtonType::East),
+    (Action::Up, GamepadButtonType::DPadUp),
+    (Action::Down, GamepadButtonType::DPadDown),
+    (Action::Left, GamepadButtonType::DPadLeft),
+    (Action::Right, GamepadButtonType::DPadRight),
+];
+
+// the keyboard and mouse backend, the bindings come from the settings file
+fn keyboard_actions(
+    settings: &Settings,
+    keys: &Input<KeyCode>,
+    buttons: &Input<MouseButton>,
+) -> Vec<Action> {
+    let bindings = &settings.key_bindings;
+    let mut held: Vec<Action> = KEYBOARD_ACTIONS
+        .iter()
+        .filter(|(_, key)| keys.pressed(*key))
+        .map(|(action, _)| *action)
+        .collect();
+    for (action, key) in [
+        (Action::Up, bindings.up),
+        (Action::Down, bindings.down),
+        (Action::Left, bindings.left),
+        (Action::Right, bindings.right),
+        (Action::Pause, bindings.pause),
+        (Action::Fire, bindings.fire_key),
+    ] {
+        if keys.pressed(key) {
+            held.push(action);
+        }
+    }
+    if buttons.pressed(bindings.shoot) {
+        held.push(Action::Fire);
+    }
+    held
+}
+
+fn keyboard_move_axis(settings: &Settings, keys: &Input<KeyCode>) -> Vec2 {
+    let bindings = &settings.key_bindings;
+    let mut axis = Vec2::ZERO;
+    if keys.pressed(bindings.up) {
+        axis.y += 1.0;
+    }
+    if keys.pressed(bindings.down) {
+        axis.y -= 1.0;
+    }
+    if keys.pressed(bindings.left) {
+        axis.x -= 1.0;
+    }
+    if keys.pressed(bindings.right) {
+        axis.x += 1.0;
+    }
+    axis
+}
+
+fn keyboard_aim(keys: &Input<KeyCode>) -> Option<Vec2> {
+    let mut aim = Vec2::ZERO;
+    for (key, direction) in AIM_KEYS {
+        if keys.pressed(key) {
+            aim += direction;
+        }
+    }
+    aim.try_normalize()
+}
+
+// the inverse of what the camera does when it draws, from window position to world position
+fn cursor_to_world(
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    cursor: Vec2,
+) -> Option<Vec2> {
+    let viewport_size = camera.logical_viewport_size()?;
+    let ndc = cursor / viewport_size * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    Some(ndc_to_world.project_point3(ndc.extend(-1.0)).truncate())
+}
+
+fn stick(
+    axes: &Axis<GamepadAxis>,
+    gamepad: Gamepad,
+    x: GamepadAxisType,
+    y: GamepadAxisType,
+) -> Vec2 {
+    Vec2::new(
+        axes.get(GamepadAxis::new(gamepad, x)).unwrap_or(0.0),
+        axes.get(GamepadAxis::new(gamepad, y)).unwrap_or(0.0),
+    )
+}
+
+// windows and gamepads are optional so the actions can be driven by synthetic input in an
+// app without a window
+fn update_actions(
+    mut actions: ResMut<Input<Action>>,
+    mut action_axes: ResMut<ActionAxes>,
+    settings: Res<Settings>,
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<MouseButton>>,
+    gamepads: Option<Res<Gamepads>>,
+    gamepad_buttons: Option<Res<Input<GamepadButton>>>,
+    gamepad_axes: Option<Res<Axis<GamepadAxis>>>,
+    windows: Option<Res<Windows>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<CameraFlag>>,
+    mut aim_source: Local<AimSource>,
+    mut last_cursor: Local<Option<Vec2>>,
+) {
+    let mut held = keyboard_actions(&settings, &keys, &buttons);
+    let mut move_axis = keyboard_move_axis(&settings, &keys);
+    let mut stick_aim = None;
+
+    if let (Some(gamepads), Some(gamepad_buttons), Some(gamepad_axes)) =
+        (gamepads, gamepad_buttons, gamepad_axes)
+    {
+        for gamepad in gamepads.iter() {
+            for (action, button_type) in GAMEPAD_ACTIONS {
+                if gamepad_buttons.pressed(GamepadButton::new(*gamepad, button_type)) {
+                    held.push(action);
+                }
+            }
+            move_axis += stick(
+                &gamepad_axes,
+                *gamepad,
+                GamepadAxisType::LeftStickX,
+                GamepadAxisType::LeftStickY,
+            );
+            let right_stick = stick(
+                &gamepad_axes,
+                *gamepad,
+                GamepadAxisType::RightStickX,
+                GamepadAxisType::RightStickY,
+            );
+            if right_stick.length() > AIM_STICK_THRESHOLD {
+                stick_aim = Some(right_stick.normalize());
+            }
+        }
+    }
+
+    // press and release only report a change once, so just_pressed works as it does for keys
+    let all_actions = [
+        Action::Fire,
+        Action::Pause,
+        Action::Confirm,
+        Action::Cancel,
+        Action::Up,
+        Action::Down,
+        Action::Left,
+        Action::Right,
+    ];
+    actions.clear();
+    for action in all_actions {
+        if held.contains(&action) {
+            actions.press(action);
+        } else {
+            actions.release(action);
+        }
+    }
+
+    // whichever of the mouse, the right stick and the arrow keys was used last does the aiming
+    let cursor = windows
+        .as_ref()
+        .and_then(|windows| windows.get_primary()?.cursor_position());
+    let key_aim = keyboard_aim(&keys);
+    if stick_aim.is_some() {
+        *aim_source = AimSource::Gamepad;
+    } else if key_aim.is_some() {
+        *aim_source = AimSource::Keys;
+    } else if cursor.is_some() && (cursor != *last_cursor || buttons.get_just_pressed().count() > 0)
+    {
+        *aim_source = AimSource::Mouse;
+    }
+    *last_cursor = cursor;
+
+    action_axes.move_axis = move_axis.clamp_length_max(1.0);
+    action_axes.aim = match *aim_source {
+        AimSource::Gamepad => stick_aim.map(Aim::Direction),
+        AimSource::Keys => key_aim.map(Aim::Direction),
+        AimSource::Mouse => cursor.and_then(|cursor| {
+            let (camera, camera_transform) = camera_query.get_single().ok()?;
+            cursor_to_world(camera, camera_transform, cursor).map(Aim::Point)
+        }),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::input::gamepad::{gamepad_connection_system, GamepadEvent, GamepadEventType};
+
+    use super::*;
+
+    const GAMEPAD: Gamepad = Gamepad { id: 0 };
+
+    // no window, no camera and no gamepads until one is plugged in
+    fn setup(settings: Settings) -> (World, SystemStage) {
+        let mut world = World::new();
+        world.insert_resource(settings);
+        world.init_resource::<Input<Action>>();
+        world.init_resource::<ActionAxes>();
+        world.init_resource::<Input<KeyCode>>();
+        world.init_resource::<Input<MouseButton>>();
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(update_actions);
+        (world, stage)
+    }
+
+    fn plug_in_gamepad(world: &mut World) {
+        world.init_resource::<Gamepads>();
+        world.init_resource::<Input<GamepadButton>>();
+        world.init_resource::<Axis<GamepadAxis>>();
+        world.init_resource::<Events<GamepadEvent>>();
+        world.send_event(GamepadEvent::new(GAMEPAD, GamepadEventType::Connected));
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(gamepad_connection_system);
+        stage.run(world);
+    }
+
+    // one frame with exactly these keys held down
+    fn frame(world: &mut World, stage: &mut SystemStage, held: &[KeyCode]) {
+        let mut keys = world.resource_mut::<Input<KeyCode>>();
+        keys.clear();
+        let released: Vec<KeyCode> = keys
+            .get_pressed()
+            .copied()
+            .filter(|key| !held.contains(key))
+            .collect();
+        for key in released {
+            keys.release(key);
+        }
+        for key in held {
+            keys.press(*key);
+        }
+        stage.run(world);
+    }
+
+    #[test]
+    fn just_pressed_only_on_the_first_frame() {
+        let (mut world, mut stage) = setup(Settings::default());
+        frame(&mut world, &mut stage, &[KeyCode::W]);
+        let actions = world.resource::<Input<Action>>();
+        assert!(actions.pressed(Action::Up) && actions.just_pressed(Action::Up));
+
+        frame(&mut world, &mut stage, &[KeyCode::W]);
+        let actions = world.resource::<Input<Action>>();
+        assert!(actions.pressed(Action::Up) && !actions.just_pressed(Action::Up));
+
+        frame(&mut world, &mut stage, &[]);
+        let actions = world.resource::<Input<Action>>();
+        assert!(!actions.pressed(Action::Up) && actions.just_released(Action::Up));
+    }
+
+    #[test]
+    fn rebound_keys() {
+        let mut settings = Settings::default();
+        settings.key_bindings.up = KeyCode::I;
+        settings.key_bindings.fire_key = KeyCode::F;
+        let (mut world, mut stage) = setup(settings);
+
+        frame(&mut world, &mut stage, &[KeyCode::W, KeyCode::Space]);
+        assert!(world
+            .resource::<Input<Action>>()
+            .get_pressed()
+            .next()
+            .is_none());
+        assert_eq!(world.resource::<ActionAxes>().move_axis, Vec2::ZERO);
+
+        frame(&mut world, &mut stage, &[KeyCode::I, KeyCode::F]);
+        let actions = world.resource::<Input<Action>>();
+        assert!(actions.just_pressed(Action::Up) && actions.just_pressed(Action::Fire));
+        assert_eq!(world.resource::<ActionAxes>().move_axis, Vec2::Y);
+    }
+
+    #[test]
+    fn arrow_keys_only_aim() {
+        let (mut world, mut stage) = setup(Settings::default());
+        frame(&mut world, &mut stage, &[KeyCode::Up, KeyCode::Right]);
+        assert!(world
+            .resource::<Input<Action>>()
+            .get_pressed()
+            .next()
+            .is_none());
+        let axes = world.resource::<ActionAxes>();
+        assert_eq!(axes.move_axis, Vec2::ZERO);
+        assert_eq!(axes.aim, Some(Aim::Direction(Vec2::ONE.normalize())));
+
+        // and the movement keys don't aim
+        frame(&mut world, &mut stage, &[]);
+        frame(&mut world, &mut stage, &[KeyCode::S]);
+        assert!(world.resource::<Input<Action>>().just_pressed(Action::Down));
+        assert_eq!(world.resource::<ActionAxes>().aim, None);
+    }
+
+    #[test]
+    fn move_axis_never_longer_than_one() {
+        let (mut world, mut stage) = setup(Settings::default());
+        frame(&mut world, &mut stage, &[KeyCode::W, KeyCode::D]);
+        let move_axis = world.resource::<ActionAxes>().move_axis;
+        assert!((move_axis.length() - 1.0).abs() < 1e-5);
+        assert!((move_axis.x - move_axis.y).abs() < 1e-5);
+
+        // the stick and the keys pushing the same way don't add up to more than full speed
+        plug_in_gamepad(&mut world);
+        let mut axes = world.resource_mut::<Axis<GamepadAxis>>();
+        axes.set(GamepadAxis::new(GAMEPAD, GamepadAxisType::LeftStickX), 1.0);
+        frame(&mut world, &mut stage, &[KeyCode::D]);
+        assert_eq!(world.resource::<ActionAxes>().move_axis, Vec2::X);
+    }
+
+    #[test]
+    fn gamepad_buttons_and_aim() {
+        let (mut world, mut stage) = setup(Settings::default());
+        plug_in_gamepad(&mut world);
+        let mut buttons = world.resource_mut::<Input<GamepadButton>>();
+        buttons.press(GamepadButton::new(GAMEPAD, GamepadButtonType::Start));
+        let mut axes = world.resource_mut::<Axis<GamepadAxis>>();
+        axes.set(
+            GamepadAxis::new(GAMEPAD, GamepadAxisType::RightStickY),
+            -0.5,
+        );
+        frame(&mut world, &mut stage, &[]);
+
+        assert!(world
+            .resource::<Input<Action>>()
+            .just_pressed(Action::Pause));
+        assert_eq!(
+            world.resource::<ActionAxes>().aim,
+            Some(Aim::Direction(-Vec2::Y))
+        );
+    }
+
+    #[test]
+    fn escape_is_cancel_and_pause() {
+        let (mut world, mut stage) = setup(Settings::default());
+        frame(&mut world, &mut stage, &[KeyCode::Escape]);
+        let mut actions = world.resource_mut::<Input<Action>>();
+        assert!(actions.just_pressed(Action::Cancel) && actions.just_pressed(Action::Pause));
+
+        // what the settings menu does when it's left, so the pause menu under it stays open
+        actions.clear_just_pressed(Action::Cancel);
+        actions.clear_just_pressed(Action::Pause);
+        assert!(!actions.just_pressed(Action::Pause));
+
+        // held down into the next frame it isn't pressed again
+        frame(&mut world, &mut stage, &[KeyCode::Escape]);
+        assert!(!world
+            .resource::<Input<Action>>()
+            .just_pressed(Action::Pause));
+    }
+
+    #[test]
+    fn pause_rebound_off_escape() {
+        let mut settings = Settings::default();
+        settings.key_bindings.pause = KeyCode::P;
+        let (mut world, mut stage) = setup(settings);
+        frame(&mut world, &mut stage, &[KeyCode::Escape]);
+        let actions = world.resource::<Input<Action>>();
+        assert!(actions.just_pressed(Action::Cancel) && !actions.pressed(Action::Pause));
+    }
+}
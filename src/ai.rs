@@ -0,0 +1,515 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom};
+
+use crate::{
+    components::{Enemy, LivingEntity, Player},
+    enemy::enemy_vision,
+    tilegrid::TILE_STEP,
+    GameState,
+};
+
+// seconds an enemy keeps chasing, or running, after it loses sight of the player
+const LOSE_TRACK_TIME: f32 = 3.0;
+// seconds spent turning towards a noise before going to look
+const ALERT_TIME: f32 = 1.0;
+// seconds spent going to and looking around the last place the player was seen
+const SEARCH_TIME: f32 = 6.0;
+// seconds stood at each patrol point
+const IDLE_TIME: f32 = 2.0;
+// a patrol leg that takes longer than this is given up on, the point might be unreachable
+const PATROL_LEG_TIME: f32 = 8.0;
+// radians a second
+const TURN_SPEED: f32 = 4.0;
+// how far ahead of itself a fleeing enemy aims
+const FLEE_DISTANCE: f32 = 200.0;
+// how many open tiles of its block an enemy without an authored route patrols between
+const RANDOM_ROUTE_POINTS: usize = 3;
+const DEBUG_LABEL_OFFSET: Vec3 = Vec3::new(0.0, 20.0, 10.0);
+
+pub struct AiPlugin;
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<NoiseEvent>()
+            .init_resource::<AiDebug>()
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing).with_system(update_ai.after(enemy_vision)),
+            )
+            .add_system(toggle_ai_debug)
+            .add_system(update_ai_debug.after(toggle_ai_debug));
+    }
+}
+
+// something the enemies within radius can hear, like the player firing
+pub struct NoiseEvent {
+    pub position: Vec3,
+    pub radius: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AiState {
+    // stood still, waiting to move on to the next patrol point if it has a route
+    Idle,
+    // walking to the next point of its route
+    Patrol,
+    // heard something and is turning to face it
+    Alert { toward: Vec3 },
+    Chase,
+    // going to where the player was last seen or a noise came from, then looking around
+    Search { position: Vec3 },
+    // low on health and running from the player
+    Flee,
+}
+
+impl AiState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AiState::Idle => "idle",
+            AiState::Patrol => "patrol",
+            AiState::Alert { .. } => "alert",
+            AiState::Chase => "chase",
+            AiState::Search { .. } => "search",
+            AiState::Flee => "flee",
+        }
+    }
+
+    // fraction of the enemy's top speed it moves at
+    pub fn speed_scale(&self) -> f32 {
+        match self {
+            AiState::Patrol => 0.5,
+            AiState::Search { .. } => 0.75,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct AiBrain {
+    pub state: AiState,
+    // seconds since the state last changed
+    pub state_time: f32,
+    // seconds since the player was last seen
+    pub unseen_time: f32,
+    // the fraction of health at or below which the enemy runs, 0 never runs
+    pub flee_health: f32,
+    pub route: Vec<Vec3>,
+    pub route_index: usize,
+}
+
+impl AiBrain {
+    pub fn new(route: Vec<Vec3>, flee_health: f32) -> AiBrain {
+        AiBrain {
+            state: AiState::Idle,
+            state_time: 0.0,
+            unseen_time: LOSE_TRACK_TIME,
+            flee_health,
+            route,
+            route_index: 0,
+        }
+    }
+
+    // the place the current state is walking to, if it's somewhere fixed
+    fn goal(&self) -> Option<Vec3> {
+        match self.state {
+            AiState::Patrol => self.route.get(self.route_index).copied(),
+            AiState::Search { position } => Some(position),
+            _ => None,
+        }
+    }
+
+    fn arrived(&self, position: Vec3) -> bool {
+        self.goal()
+            .is_some_and(|goal| goal.truncate().distance(position.truncate()) < TILE_STEP)
+    }
+
+    // where the enemy should be heading this frame, None to stand still
+    fn move_target(&self, position: Vec3, player_position: Vec3) -> Option<Vec3> {
+        match self.state {
+            AiState::Idle | AiState::Alert { .. } => None,
+            AiState::Chase => Some(player_position),
+            AiState::Flee => {
+                Some(position + (position - player_position).normalize_or_zero() * FLEE_DISTANCE)
+            }
+            AiState::Patrol | AiState::Search { .. } if self.arrived(position) => None,
+            AiState::Patrol | AiState::Search { .. } => self.goal(),
+        }
+    }
+}
+
+// everything next_state needs to know about an enemy this frame
+pub struct AiSenses {
+    pub sees_player: bool,
+    // seconds since the player was last seen
+    pub unseen_time: f32,
+    pub last_known_position: Option<Vec3>,
+    // where the last noise it could hear this frame came from
+    pub heard: Option<Vec3>,
+    pub health_fraction: f32,
+    // at the end of the current patrol leg or search
+    pub arrived: bool,
+    pub has_route: bool,
+}
+
+// seeing the player beats everything, then losing track of them, then noises, then whatever
+// the enemy was doing on its own
+pub fn next_state(state: AiState, state_time: f32, senses: &AiSenses, flee_health: f32) -> AiState {
+    let rest = if senses.has_route {
+        AiState::Patrol
+    } else {
+        AiState::Idle
+    };
+    if senses.sees_player {
+        return if senses.health_fraction <= flee_health {
+            AiState::Flee
+        } else {
+            AiState::Chase
+        };
+    }
+
+    match state {
+        AiState::Chase | AiState::Flee if senses.unseen_time < LOSE_TRACK_TIME => return state,
+        AiState::Chase => {
+            return match senses.last_known_position {
+                Some(position) => AiState::Search { position },
+                None => rest,
+            }
+        }
+        AiState::Flee => return rest,
+        _ => {}
+    }
+    if let Some(toward) = senses.heard {
+        return AiState::Alert { toward };
+    }
+
+    match state {
+        AiState::Alert { toward } if state_time >= ALERT_TIME => {
+            AiState::Search { position: toward }
+        }
+        AiState::Search { .. } if state_time >= SEARCH_TIME => rest,
+        AiState::Patrol if senses.arrived || state_time >= PATROL_LEG_TIME => AiState::Idle,
+        AiState::Idle if senses.has_route && state_time >= IDLE_TIME => AiState::Patrol,
+        _ => state,
+    }
+}
+
+// enemies in a block with patrol points walk between them in reading order, the rest make up
+// a route from where they start and a few open tiles of their block
+pub fn patrol_route(
+    start: Vec3,
+    patrol_points: &[Vec3],
+    open_tiles: &[Vec3],
+    rng: &mut StdRng,
+) -> Vec<Vec3> {
+    if !patrol_points.is_empty() {
+        return patrol_points.to_vec();
+    }
+    let mut route = vec![start];
+    route.extend(
+        open_tiles
+            .choose_multiple(rng, RANDOM_ROUTE_POINTS)
+            .copied(),
+    );
+    route
+}
+
+// turns facing at most max_angle radians towards direction
+fn turn_towards(facing: Vec3, direction: Vec2, max_angle: f32) -> Vec3 {
+    let facing = facing.truncate();
+    if direction.length_squared() == 0.0 {
+        return facing.extend(0.0);
+    }
+    let angle = facing.angle_between(direction).clamp(-max_angle, max_angle);
+    Vec2::from_angle(angle).rotate(facing).extend(0.0)
+}
+
+fn update_ai(
+    mut noise_events: EventReader<NoiseEvent>,
+    mut enemy_query: Query<(&mut Enemy, &mut AiBrain, &Transform, &LivingEntity)>,
+    player_query: Query<&Transform, (With<Player>, Without<Enemy>)>,
+    time: Res<Time>,
+) {
+    let player_transform = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let noises: Vec<&NoiseEvent> = noise_events.iter().collect();
+    let delta = time.delta_seconds();
+
+    for (mut enemy, mut brain, transform, living) in enemy_query.iter_mut() {
+        let position = transform.translation;
+        brain.state_time += delta;
+        if enemy.sees_player {
+            brain.unseen_time = 0.0;
+            enemy.last_known_position = Some(player_transform.translation);
+        } else {
+            brain.unseen_time += delta;
+        }
+
+        let senses = AiSenses {
+            sees_player: enemy.sees_player,
+            unseen_time: brain.unseen_time,
+            last_known_position: enemy.last_known_position,
+            heard: noises
+                .iter()
+                .filter(|noise| noise.position.distance(position) <= noise.radius)
+                .map(|noise| noise.position)
+                .next_back(),
+            health_fraction: living.health / living.max_health,
+            arrived: brain.arrived(position),
+            has_route: !brain.route.is_empty(),
+        };
+        let next = next_state(brain.state, brain.state_time, &senses, brain.flee_health);
+        if next != brain.state {
+            // whether it got there, gave up or was interrupted, the next leg goes to the next point
+            if brain.state == AiState::Patrol && !brain.route.is_empty() {
+                brain.route_index = (brain.route_index + 1) % brain.route.len();
+            }
+            brain.state = next;
+            brain.state_time = 0.0;
+        }
+
+        enemy.move_target = brain.move_target(position, player_transform.translation);
+        let max_turn = TURN_SPEED * delta;
+        match brain.state {
+            AiState::Alert { toward } => {
+                enemy.facing = turn_towards(enemy.facing, (toward - position).truncate(), max_turn);
+            }
+            // looks all the way round once it gets there
+            AiState::Search { .. } if senses.arrived => {
+                enemy.facing = Vec2::from_angle(max_turn)
+                    .rotate(enemy.facing.truncate())
+                    .extend(0.0);
+            }
+            _ => {}
+        }
+    }
+}
+
+// F3 shows the state of every enemy above it
+#[derive(Default)]
+struct AiDebug(bool);
+
+#[derive(Component)]
+struct AiDebugLabel(Entity);
+
+fn toggle_ai_debug(keys: Res<Input<KeyCode>>, mut debug: ResMut<AiDebug>) {
+    if keys.just_pressed(KeyCode::F3) {
+        debug.0 = !debug.0;
+    }
+}
+
+// the labels aren't children of the enemies so they don't get left behind when one is despawned
+fn update_ai_debug(
+    mut commands: Commands,
+    debug: Res<AiDebug>,
+    assets: Res<AssetServer>,
+    enemy_query: Query<(Entity, &Transform, &AiBrain)>,
+    mut label_query: Query<(Entity, &AiDebugLabel, &mut Text, &mut Transform), Without<AiBrain>>,
+) {
+    let mut labelled = Vec::new();
+    for (label, AiDebugLabel(enemy), mut text, mut transform) in label_query.iter_mut() {
+        match enemy_query.get(*enemy) {
+            Ok((_, enemy_transform, brain)) if debug.0 => {
+                text.sections[0].value = brain.state.name().to_string();
+                transform.translation = enemy_transform.translation + DEBUG_LABEL_OFFSET;
+                labelled.push(*enemy);
+            }
+            _ => commands.entity(label).despawn(),
+        }
+    }
+    if !debug.0 {
+        return;
+    }
+
+    for (enemy, transform, brain) in enemy_query.iter() {
+        if labelled.contains(&enemy) {
+            continue;
+        }
+        let text = Text::from_section(
+            brain.state.name(),
+            TextStyle {
+                font: assets.load("Hack-Regular.ttf"),
+                font_size: 14.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_alignment(TextAlignment::CENTER);
+        commands
+            .spawn_bundle(Text2dBundle {
+                text,
+                transform: Transform::from_translation(transform.translation + DEBUG_LABEL_OFFSET),
+                ..default()
+            })
+            .insert(AiDebugLabel(enemy))
+            .insert(Name::new("AI debug label"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    const FLEE_HEALTH: f32 = 0.25;
+
+    // full health, nothing seen or heard, with a route to walk
+    fn calm() -> AiSenses {
+        AiSenses {
+            sees_player: false,
+            unseen_time: LOSE_TRACK_TIME,
+            last_known_position: None,
+            heard: None,
+            health_fraction: 1.0,
+            arrived: false,
+            has_route: true,
+        }
+    }
+
+    fn seen() -> AiSenses {
+        AiSenses {
+            sees_player: true,
+            unseen_time: 0.0,
+            last_known_position: Some(Vec3::X),
+            ..calm()
+        }
+    }
+
+    #[test]
+    fn idle_hears_a_noise() {
+        let senses = AiSenses {
+            heard: Some(Vec3::Y),
+            ..calm()
+        };
+        let state = next_state(AiState::Idle, 0.0, &senses, FLEE_HEALTH);
+        assert_eq!(state, AiState::Alert { toward: Vec3::Y });
+    }
+
+    #[test]
+    fn alert_sees_the_player() {
+        let state = next_state(
+            AiState::Alert { toward: Vec3::Y },
+            0.0,
+            &seen(),
+            FLEE_HEALTH,
+        );
+        assert_eq!(state, AiState::Chase);
+    }
+
+    #[test]
+    fn chase_flees_when_hurt() {
+        let hurt = AiSenses {
+            health_fraction: FLEE_HEALTH,
+            ..seen()
+        };
+        assert_eq!(
+            next_state(AiState::Chase, 0.0, &hurt, FLEE_HEALTH),
+            AiState::Flee
+        );
+        // never runs with a flee_health of 0
+        assert_eq!(next_state(AiState::Chase, 0.0, &hurt, 0.0), AiState::Chase);
+    }
+
+    #[test]
+    fn alert_goes_to_look() {
+        let toward = Vec3::Y;
+        let alert = AiState::Alert { toward };
+        assert_eq!(
+            next_state(alert, ALERT_TIME * 0.5, &calm(), FLEE_HEALTH),
+            alert
+        );
+        assert_eq!(
+            next_state(alert, ALERT_TIME, &calm(), FLEE_HEALTH),
+            AiState::Search { position: toward }
+        );
+    }
+
+    #[test]
+    fn chase_loses_track() {
+        let losing = AiSenses {
+            unseen_time: LOSE_TRACK_TIME * 0.5,
+            last_known_position: Some(Vec3::X),
+            ..calm()
+        };
+        assert_eq!(
+            next_state(AiState::Chase, 0.0, &losing, FLEE_HEALTH),
+            AiState::Chase
+        );
+
+        let lost = AiSenses {
+            last_known_position: Some(Vec3::X),
+            ..calm()
+        };
+        assert_eq!(
+            next_state(AiState::Chase, 0.0, &lost, FLEE_HEALTH),
+            AiState::Search { position: Vec3::X }
+        );
+        assert_eq!(
+            next_state(AiState::Flee, 0.0, &lost, FLEE_HEALTH),
+            AiState::Patrol
+        );
+    }
+
+    #[test]
+    fn search_gives_up_back_to_patrol() {
+        let search = AiState::Search { position: Vec3::X };
+        assert_eq!(
+            next_state(search, SEARCH_TIME * 0.5, &calm(), FLEE_HEALTH),
+            search
+        );
+        assert_eq!(
+            next_state(search, SEARCH_TIME, &calm(), FLEE_HEALTH),
+            AiState::Patrol
+        );
+
+        let no_route = AiSenses {
+            has_route: false,
+            ..calm()
+        };
+        assert_eq!(
+            next_state(search, SEARCH_TIME, &no_route, FLEE_HEALTH),
+            AiState::Idle
+        );
+    }
+
+    #[test]
+    fn patrol_and_idle_take_turns() {
+        let arrived = AiSenses {
+            arrived: true,
+            ..calm()
+        };
+        assert_eq!(
+            next_state(AiState::Patrol, 0.0, &arrived, FLEE_HEALTH),
+            AiState::Idle
+        );
+        // a leg that never gets there is given up on
+        assert_eq!(
+            next_state(AiState::Patrol, PATROL_LEG_TIME, &calm(), FLEE_HEALTH),
+            AiState::Idle
+        );
+        assert_eq!(
+            next_state(AiState::Idle, IDLE_TIME, &calm(), FLEE_HEALTH),
+            AiState::Patrol
+        );
+    }
+
+    #[test]
+    fn patrol_route_from_seed() {
+        let open_tiles: Vec<Vec3> = (0..20).map(|x| Vec3::new(x as f32, 0.0, 0.0)).collect();
+        let route = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            patrol_route(Vec3::Y, &[], &open_tiles, &mut rng)
+        };
+        assert_eq!(route(3), route(3));
+        assert_eq!(route(3).len(), RANDOM_ROUTE_POINTS + 1);
+        assert_eq!(route(3)[0], Vec3::Y);
+
+        // authored patrol points are walked as they are
+        let points = [Vec3::X, Vec3::Y];
+        let mut rng = StdRng::seed_from_u64(3);
+        assert_eq!(
+            patrol_route(Vec3::ZERO, &points, &open_tiles, &mut rng),
+            points
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use bevy::prelude::*;
 
+#[derive(Default)]
 pub struct AsciiSheet(Handle<TextureAtlas>);
 
 pub struct AsciiPlugin;
@@ -0,0 +1,253 @@
+use bevy::prelude::*;
+
+use crate::{
+    ai::{AiBrain, AiState},
+    bullet::BulletPool,
+    camera::ScreenShake,
+    components::{Boss, Bullet, BulletSpawner, LivingEntity, LockedExit, Player, Team},
+    enemydef::{BossPhase, PatternShape},
+    ui::make_text_bundle,
+    GameState,
+};
+
+// how faded a locked exit is drawn
+const LOCKED_EXIT_ALPHA: f32 = 0.25;
+const HEALTH_BAR_HEIGHT: f32 = 16.0;
+const HEALTH_BAR_BACKGROUND: Color = Color::rgb(0.2, 0.2, 0.2);
+const HEALTH_BAR_FILL: Color = Color::rgb(0.85, 0.15, 0.15);
+
+pub struct BossPlugin;
+
+impl Plugin for BossPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(boss_phases)
+                .with_system(boss_fire.after(boss_phases))
+                .with_system(lock_exits)
+                .with_system(unlock_exits)
+                .with_system(spawn_boss_health_bar)
+                .with_system(update_boss_health_bar),
+        );
+    }
+}
+
+impl Boss {
+    // phases have already been checked to have at least one phase
+    pub fn new(phases: Vec<BossPhase>) -> Boss {
+        let spawners = phase_spawners(&phases[0]);
+        Boss {
+            phases,
+            phase: 0,
+            spawners,
+        }
+    }
+}
+
+fn phase_spawners(phase: &BossPhase) -> Vec<BulletSpawner> {
+    phase
+        .patterns
+        .iter()
+        .map(|pattern| BulletSpawner {
+            pattern: *pattern,
+            timer: Timer::from_seconds(pattern.interval, true),
+            angle: 0.0,
+        })
+        .collect()
+}
+
+// the last phase the boss has lost enough health to be in, the phases are in order of
+// falling health
+pub fn phase_for_health(phases: &[BossPhase], health_fraction: f32) -> usize {
+    phases
+        .iter()
+        .rposition(|phase| health_fraction <= phase.health)
+        .unwrap_or(0)
+}
+
+// the direction of every bullet in one volley. angle is where a ring starts in degrees, aim
+// is which way the player is
+pub fn pattern_directions(shape: PatternShape, angle: f32, aim: Vec2) -> Vec<Vec2> {
+    match shape {
+        PatternShape::Ring { bullets, .. } => (0..bullets)
+            .map(|bullet| {
+                let degrees = angle + 360.0 * bullet as f32 / bullets as f32;
+                Vec2::from_angle(degrees.to_radians())
+            })
+            .collect(),
+        PatternShape::Fan { bullets, spread } => {
+            let spread = spread.to_radians();
+            (0..bullets)
+                .map(|bullet| {
+                    let offset = if bullets > 1 {
+                        spread * (bullet as f32 / (bullets - 1) as f32 - 0.5)
+                    } else {
+                        0.0
+                    };
+                    Vec2::from_angle(offset).rotate(aim)
+                })
+                .collect()
+        }
+    }
+}
+
+// moving on to a new phase starts its patterns from scratch and shakes the screen so it's
+// noticed
+fn boss_phases(
+    mut boss_query: Query<(&mut Boss, &LivingEntity)>,
+    mut shake_events: EventWriter<ScreenShake>,
+) {
+    for (mut boss, living) in boss_query.iter_mut() {
+        let phase = phase_for_health(&boss.phases, living.health / living.max_health);
+        if phase != boss.phase {
+            boss.spawners = phase_spawners(&boss.phases[phase]);
+            boss.phase = phase;
+            shake_events.send(ScreenShake { trauma: 0.6 });
+        }
+    }
+}
+
+// the patterns only fire once the boss has noticed the player
+fn boss_fire(
+    mut commands: Commands,
+    mut bullet_pool: ResMut<BulletPool>,
+    mut boss_query: Query<(&mut Boss, &AiBrain, &Transform)>,
+    player_query: Query<&Transform, (With<Player>, Without<Boss>)>,
+    time: Res<Time>,
+) {
+    let player_transform = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    for (mut boss, brain, transform) in boss_query.iter_mut() {
+        if brain.state != AiState::Chase {
+            continue;
+        }
+        // fans need somewhere to point even with the player right on top of the boss
+        let aim = (player_transform.translation - transform.translation)
+            .truncate()
+            .try_normalize()
+            .unwrap_or(Vec2::Y);
+        for spawner in boss.spawners.iter_mut() {
+            spawner.timer.tick(time.delta());
+            if !spawner.timer.just_finished() {
+                continue;
+            }
+            let pattern = spawner.pattern;
+            for direction in pattern_directions(pattern.shape, spawner.angle, aim) {
+                let bullet = Bullet {
+                    damage: pattern.damage,
+                    ..Bullet::new(Team::Enemy, direction * pattern.bullet_speed)
+                };
+                bullet_pool.fire(&mut commands, transform.translation, bullet);
+            }
+            if let PatternShape::Ring { spin, .. } = pattern.shape {
+                spawner.angle = (spawner.angle + spin) % 360.0;
+            }
+        }
+    }
+}
+
+fn lock_exits(mut exit_query: Query<&mut TextureAtlasSprite, Added<LockedExit>>) {
+    for mut sprite in exit_query.iter_mut() {
+        sprite.color.set_a(LOCKED_EXIT_ALPHA);
+    }
+}
+
+// once every boss on the stage is dead the exits can be used
+fn unlock_exits(
+    mut commands: Commands,
+    boss_query: Query<(), With<Boss>>,
+    mut exit_query: Query<(Entity, &mut TextureAtlasSprite), With<LockedExit>>,
+) {
+    if !boss_query.is_empty() {
+        return;
+    }
+    for (exit, mut sprite) in exit_query.iter_mut() {
+        sprite.color.set_a(1.0);
+        commands.entity(exit).remove::<LockedExit>();
+    }
+}
+
+// the bar and the name under it, both go once the boss they belong to is gone
+#[derive(Component)]
+struct BossHealthBar(Entity);
+
+#[derive(Component)]
+struct BossHealthFill(Entity);
+
+fn spawn_boss_health_bar(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    boss_query: Query<(Entity, &Name), Added<Boss>>,
+) {
+    for (boss, name) in boss_query.iter() {
+        commands
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(10.0),
+                        left: Val::Percent(30.0),
+                        ..default()
+                    },
+                    size: Size::new(Val::Percent(40.0), Val::Px(HEALTH_BAR_HEIGHT)),
+                    ..default()
+                },
+                color: HEALTH_BAR_BACKGROUND.into(),
+                ..default()
+            })
+            .insert(BossHealthBar(boss))
+            .insert(Name::new("Boss health bar"))
+            .with_children(|parent| {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                            ..default()
+                        },
+                        color: HEALTH_BAR_FILL.into(),
+                        ..default()
+                    })
+                    .insert(BossHealthFill(boss));
+            });
+
+        let label = make_text_bundle(
+            &mut commands,
+            &assets,
+            20.0,
+            name.as_str().to_string(),
+            Color::WHITE,
+            Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(15.0 + HEALTH_BAR_HEIGHT),
+                    left: Val::Percent(30.0),
+                    ..default()
+                },
+                ..default()
+            },
+        );
+        commands.entity(label).insert(BossHealthBar(boss));
+    }
+}
+
+fn update_boss_health_bar(
+    mut commands: Commands,
+    boss_query: Query<&LivingEntity, With<Boss>>,
+    bar_query: Query<(Entity, &BossHealthBar)>,
+    mut fill_query: Query<(&BossHealthFill, &mut Style)>,
+) {
+    for (bar, BossHealthBar(boss)) in bar_query.iter() {
+        if boss_query.get(*boss).is_err() {
+            commands.entity(bar).despawn_recursive();
+        }
+    }
+    for (BossHealthFill(boss), mut style) in fill_query.iter_mut() {
+        if let Ok(living) = boss_query.get(*boss) {
+            let fraction = (living.health / living.max_health).max(0.0);
+            style.size.width = Val::Percent(100.0 * fraction);
+        }
+    }
+}
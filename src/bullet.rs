@@ -0,0 +1,186 @@
+use bevy::prelude::*;
+
+use crate::{
+    camera::ScreenShake,
+    components::{Bullet, Enemy, LivingEntity, Player, Team},
+    health::DamageEvent,
+    tilegrid::TileGrid,
+    GameState, TILE_SIZE,
+};
+
+const BULLET_SIZE: f32 = 6.0;
+const BULLET_DAMAGE: f32 = 1.0;
+const BULLET_KNOCKBACK: f32 = 150.0;
+// a bullet is put away after whichever of these runs out first
+const BULLET_LIFETIME: f32 = 2.0;
+const BULLET_RANGE: f32 = 1000.0;
+const ENEMY_BULLET_COLOUR: Color = Color::rgb(1.0, 0.35, 0.3);
+
+pub struct BulletPlugin;
+
+impl Plugin for BulletPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BulletPool>()
+            .add_system_to_stage(CoreStage::PreUpdate, prune_bullet_pool)
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(update_bullets));
+    }
+}
+
+impl Bullet {
+    pub fn new(team: Team, velocity: Vec2) -> Bullet {
+        Bullet {
+            team,
+            velocity,
+            damage: BULLET_DAMAGE,
+            bounces: 0,
+            piercing: 0,
+            hits: Vec::new(),
+            lifetime: Timer::from_seconds(BULLET_LIFETIME, false),
+            range: BULLET_RANGE,
+        }
+    }
+}
+
+// spent bullets are hidden and kept for the next shot instead of being despawned
+pub struct BulletPool {
+    texture: Handle<Image>,
+    free: Vec<Entity>,
+}
+
+impl FromWorld for BulletPool {
+    fn from_world(world: &mut World) -> Self {
+        let assets = world.resource::<AssetServer>();
+        BulletPool {
+            texture: assets.load("bullet.png"),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl BulletPool {
+    pub fn fire(&mut self, commands: &mut Commands, position: Vec3, bullet: Bullet) -> Entity {
+        let transform = Transform::from_translation(position);
+        // enemy bullets are tinted so they stand out from the player's
+        let sprite = Sprite {
+            color: match bullet.team {
+                Team::Player => Color::WHITE,
+                Team::Enemy => ENEMY_BULLET_COLOUR,
+            },
+            custom_size: Some(Vec2::splat(BULLET_SIZE)),
+            ..default()
+        };
+        if let Some(entity) = self.free.pop() {
+            commands
+                .entity(entity)
+                .insert(transform)
+                .insert(sprite)
+                .insert(Visibility { is_visible: true })
+                .insert(bullet);
+            return entity;
+        }
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite,
+                transform,
+                texture: self.texture.clone(),
+                ..default()
+            })
+            .insert(bullet)
+            .insert(Name::new("Bullet"))
+            .id()
+    }
+
+    fn recycle(&mut self, commands: &mut Commands, entity: Entity) {
+        commands
+            .entity(entity)
+            .remove::<Bullet>()
+            .insert(Visibility { is_visible: false });
+        self.free.push(entity);
+    }
+}
+
+// a new stage despawns everything, pooled bullets included, so they're forgotten before
+// anything can fire
+fn prune_bullet_pool(mut pool: ResMut<BulletPool>, entity_query: Query<Entity>) {
+    pool.free.retain(|entity| entity_query.get(*entity).is_ok());
+}
+
+fn update_bullets(
+    mut commands: Commands,
+    mut pool: ResMut<BulletPool>,
+    mut bullet_query: Query<(Entity, &mut Transform, &mut Bullet)>,
+    enemy_query: Query<(Entity, &Transform), (With<Enemy>, Without<Bullet>)>,
+    player_query: Query<(Entity, &Transform, &LivingEntity), (With<Player>, Without<Bullet>)>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut shake_events: EventWriter<ScreenShake>,
+    grid: Res<TileGrid>,
+    time: Res<Time>,
+) {
+    let size = Vec2::splat(BULLET_SIZE);
+    let enemies: Vec<(Entity, Vec3)> = enemy_query
+        .iter()
+        .map(|(enemy, transform)| (enemy, transform.translation))
+        .collect();
+    // enemy bullets go straight through the player while they're flashing
+    let players: Vec<(Entity, Vec3)> = player_query
+        .iter()
+        .filter(|(_, _, living)| !living.is_invulnerable())
+        .map(|(player, transform, _)| (player, transform.translation))
+        .collect();
+    for (entity, mut transform, mut bullet) in bullet_query.iter_mut() {
+        bullet.lifetime.tick(time.delta());
+        let step = bullet.velocity * time.delta_seconds();
+        bullet.range -= step.length();
+        if bullet.lifetime.finished() || bullet.range <= 0.0 {
+            pool.recycle(&mut commands, entity);
+            continue;
+        }
+
+        // each axis is tried on its own so a bounce only flips the side that hit the wall
+        let mut hit_wall = false;
+        for axis in [Vec2::X, Vec2::Y] {
+            let wish_pos = transform.translation + (step * axis).extend(0.0);
+            if grid.collides(wish_pos, size) {
+                hit_wall = true;
+                bullet.velocity *= Vec2::ONE - 2.0 * axis;
+            } else {
+                transform.translation = wish_pos;
+            }
+        }
+        if hit_wall {
+            if bullet.bounces == 0 {
+                pool.recycle(&mut commands, entity);
+                continue;
+            }
+            bullet.bounces -= 1;
+        }
+
+        // the kill is counted when the enemy dies, not here
+        let targets = match bullet.team {
+            Team::Player => &enemies,
+            Team::Enemy => &players,
+        };
+        for (target, target_translation) in targets.iter().copied() {
+            if bullet.hits.contains(&target)
+                || Vec3::distance(transform.translation, target_translation) >= TILE_SIZE
+            {
+                continue;
+            }
+            if bullet.team == Team::Enemy {
+                shake_events.send(ScreenShake { trauma: 0.5 });
+            }
+            damage_events.send(DamageEvent {
+                source: Some(entity),
+                target,
+                amount: bullet.damage,
+                knockback: bullet.velocity.normalize_or_zero() * BULLET_KNOCKBACK,
+            });
+            if bullet.piercing == 0 {
+                pool.recycle(&mut commands, entity);
+                break;
+            }
+            bullet.piercing -= 1;
+            bullet.hits.push(target);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{
+    components::{CameraFlag, Player},
+    GameState,
+};
+
+const CAMERA_Z: f32 = 600.0;
+// trauma lost per second, a full shake lasts a second
+const TRAUMA_DECAY: f32 = 1.0;
+const MAX_SHAKE_OFFSET: f32 = 20.0;
+const MAX_SHAKE_ANGLE: f32 = 0.05;
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ScreenShake>().add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(add_screen_shake)
+                .with_system(camera_follow.after(add_screen_shake)),
+        );
+    }
+}
+
+// sent by anything that wants the screen to shake, trauma adds up and is capped at 1
+pub struct ScreenShake {
+    pub trauma: f32,
+}
+
+// the edges of the current stage's border, set by draw_map_blocks. the camera doesn't show
+// anything past them unless the stage is smaller than the window
+pub struct CameraBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+#[derive(Component)]
+pub struct CameraController {
+    // how quickly the camera catches up, higher is snappier
+    pub smoothing: f32,
+    // half the size of the box in the middle of the screen the player can move in without
+    // the camera following
+    pub dead_zone: Vec2,
+    // how far ahead of the player the camera looks in the aim direction
+    pub lookahead: f32,
+    pub trauma: f32,
+    // where the camera would be without any shake
+    focus: Vec2,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        CameraController {
+            smoothing: 8.0,
+            dead_zone: Vec2::new(40.0, 30.0),
+            lookahead: 80.0,
+            trauma: 0.0,
+            focus: Vec2::ZERO,
+        }
+    }
+}
+
+impl CameraController {
+    pub fn add_trauma(&mut self, trauma: f32) {
+        self.trauma = (self.trauma + trauma).min(1.0);
+    }
+
+    // moves the focus just far enough that the target is back inside the dead zone, then eases
+    // towards that
+    fn follow(&mut self, target: Vec2, delta_seconds: f32) {
+        let offset = target - self.focus;
+        let outside = offset - offset.clamp(-self.dead_zone, self.dead_zone);
+        let ease = 1.0 - (-self.smoothing * delta_seconds).exp();
+        self.focus += outside * ease;
+    }
+}
+
+// keeps the view inside the bounds, or centres it on them if the view is bigger
+fn clamp_to_bounds(focus: Vec2, half_view: Vec2, bounds: &CameraBounds) -> Vec2 {
+    let min = bounds.min + half_view;
+    let max = bounds.max - half_view;
+    let centre = (bounds.min + bounds.max) / 2.0;
+    Vec2::new(
+        if min.x > max.x {
+            centre.x
+        } else {
+            focus.x.clamp(min.x, max.x)
+        },
+        if min.y > max.y {
+            centre.y
+        } else {
+            focus.y.clamp(min.y, max.y)
+        },
+    )
+}
+
+fn add_screen_shake(
+    mut shake_events: EventReader<ScreenShake>,
+    mut camera_query: Query<&mut CameraController, With<CameraFlag>>,
+) {
+    let trauma: f32 = shake_events.iter().map(|shake| shake.trauma).sum();
+    for mut controller in camera_query.iter_mut() {
+        controller.add_trauma(trauma);
+    }
+}
+
+fn camera_follow(
+    mut camera_query: Query<(&mut Transform, &mut CameraController), With<CameraFlag>>,
+    player_query: Query<(&Transform, &Player, ChangeTrackers<Player>), Without<CameraFlag>>,
+    bounds: Option<Res<CameraBounds>>,
+    windows: Res<Windows>,
+    time: Res<Time>,
+) {
+    let (mut camera_transform, mut controller) = match camera_query.get_single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let (player_transform, player, player_tracker) = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    let target =
+        player_transform.translation.truncate() + player.aim_direction * controller.lookahead;
+    // a new player means a new stage, jump straight there rather than sliding across the map
+    if player_tracker.is_added() {
+        controller.focus = target;
+    } else {
+        controller.follow(target, time.delta_seconds());
+    }
+
+    // the camera uses ScalingMode::WindowSize so one unit is one pixel of the window
+    if let (Some(bounds), Some(window)) = (bounds, windows.get_primary()) {
+        let half_view = Vec2::new(window.width(), window.height()) / 2.0;
+        controller.focus = clamp_to_bounds(controller.focus, half_view, &bounds);
+    }
+
+    // shake grows with the square of the trauma so small hits are subtle
+    controller.trauma = (controller.trauma - TRAUMA_DECAY * time.delta_seconds()).max(0.0);
+    let shake = controller.trauma * controller.trauma;
+    let mut rng = rand::thread_rng();
+    let shake_offset =
+        Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)) * MAX_SHAKE_OFFSET * shake;
+    let shake_angle = rng.gen_range(-1.0..1.0) * MAX_SHAKE_ANGLE * shake;
+
+    camera_transform.translation = (controller.focus + shake_offset).extend(CAMERA_Z);
+    camera_transform.rotation = Quat::from_rotation_z(shake_angle);
+}
@@ -2,7 +2,7 @@ use bevy::prelude::*;
 
 use rand::prelude::*;
 
-use crate::GameState;
+use crate::{seed::StageRng, GameState};
 
 pub struct ColourPlugin;
 
@@ -13,6 +13,7 @@ impl Plugin for ColourPlugin {
     }
 }
 
+#[derive(Clone)]
 pub struct ColourScheme {
     pub colour_0: Color,
     pub colour_1: Color,
@@ -20,13 +21,11 @@ pub struct ColourScheme {
     pub wall_colour: Color,
 }
 
-fn colourscheme_initializer(mut commands: Commands) {
-    generate_colourscheme(&mut commands);
+fn colourscheme_initializer(mut commands: Commands, mut rng: ResMut<StageRng>) {
+    generate_colourscheme(&mut commands, &mut rng.0);
 }
 
-pub fn generate_colourscheme(commands: &mut Commands) {
-    let mut rng = rand::thread_rng();
-
+pub fn generate_colourscheme(commands: &mut Commands, rng: &mut StdRng) -> ColourScheme {
     let mut hsla: Vec<f32> = Vec::new();
 
     let mut base_colour = rng.gen_range(0.0..360.0);
@@ -54,10 +53,12 @@ pub fn generate_colourscheme(commands: &mut Commands) {
     }
     let wall_colour = Color::hsla(base_colour, hsla[1] - 0.8, hsla[2] - 0.2, hsla[3]);
 
-    commands.insert_resource(ColourScheme {
+    let colours = ColourScheme {
         colour_0,
         colour_1,
         colour_2,
         wall_colour,
-    });
+    };
+    commands.insert_resource(colours.clone());
+    colours
 }
@@ -1,77 +1,218 @@
 use bevy::prelude::*;
 
+use crate::{
+    enemydef::{BossPhase, BulletPattern, LootDrop},
+    flocking::FlockWeights,
+};
+
 #[derive(Component)]
 pub struct MainMenuFlag;
 
 #[derive(Component)]
 pub struct Player {
     pub speed: f32,
-    pub health: i32,
-    pub shoot_timer: Timer,
-    pub ammo: i32,
     pub velocity: Vec3,
     pub acceleration: Vec3,
     pub max_speed: f32,
+    // kept between frames so shots still go somewhere sensible when nothing is aiming
+    pub aim_direction: Vec2,
 }
 
 #[derive(Component)]
 pub struct CameraFlag;
 
 #[derive(Component)]
-pub struct TileCollider {
-    pub size: Vec2,
-}
+pub struct TileCollider;
 
 #[derive(Component)]
 pub struct Enemy {
-    pub health: f32,
     pub vision: f32,
-    pub spotted_player: bool,
+    pub facing: Vec3,
+    // half angle in radians, None means the enemy can see all the way around
+    pub view_cone: Option<f32>,
+    pub sees_player: bool,
+    pub last_known_position: Option<Vec3>,
+    // where its AiBrain wants it to go this frame, None to stand still
+    pub move_target: Option<Vec3>,
+    // what running into the player does, 0 keeps its distance
+    pub contact_damage: f32,
 }
 
+// what an enemy might leave behind when it dies
+#[derive(Component)]
+pub struct Loot(pub Vec<LootDrop>);
+
 #[derive(Component)]
 pub struct EnemyFlock {
     pub speed: f32,
     pub max_force: f32,
     pub velocity: Vec3,
     pub acceleration: Vec3,
-    pub in_view: Vec<Transform>,
+    pub weights: FlockWeights,
 }
 
 #[derive(Component)]
 pub struct RunnerEnemy;
 
+// keeps its distance from the player, strafing around them, and stops to wind up before
+// every shot so there's a moment to get out of the way
+#[derive(Component)]
+pub struct ShooterEnemy {
+    // the distance it tries to keep from the player
+    pub range: f32,
+    // 1 or -1, which way round the player it's strafing, flipped every so often
+    pub strafe: f32,
+    pub strafe_timer: Timer,
+    pub fire_timer: Timer,
+    // counts down the wind up, with the direction that was locked in when it started
+    pub wind_up: Option<(Timer, Vec2)>,
+    pub bullet_speed: f32,
+    pub damage: f32,
+}
+
+// the enemy a boss stage is built around, it works through its phases as it loses health
+#[derive(Component)]
+pub struct Boss {
+    pub phases: Vec<BossPhase>,
+    // index into phases
+    pub phase: usize,
+    // one for each pattern of the current phase
+    pub spawners: Vec<BulletSpawner>,
+}
+
+// fires one of a boss's patterns every time its timer finishes
+pub struct BulletSpawner {
+    pub pattern: BulletPattern,
+    pub timer: Timer,
+    // degrees, where the next ring starts
+    pub angle: f32,
+}
+
 pub struct Node {
     pub x: i32,
     pub y: i32,
     pub f: f32,
     pub g: f32,
     pub h: f32,
-    pub previous: Vec<i32>,
+    pub previous: Option<IVec2>,
+    pub walkable: bool,
+    pub solid: bool,
+    pub penalty: f32,
+    pub closed: bool,
 }
 
-pub struct NodeGraph(Vec<Vec<Node>>);
+// navigation grid for the current stage, nodes are stored in columns (nodes[x][y])
+#[derive(Default)]
+pub struct NodeGraph {
+    pub origin: IVec2,
+    pub nodes: Vec<Vec<Node>>,
+}
 
-#[derive(Component)]
-pub struct Manager {
-    pub difficulty_coefficient: f32,
-    pub player_ammo: i32,
-    pub stage_number: i32,
+// waypoints are stored back to front so the next one can be popped off the end
+#[derive(Component, Default)]
+pub struct NavPath {
+    pub goal: Option<IVec2>,
+    pub waypoints: Vec<Vec3>,
 }
 
 #[derive(Component)]
 pub struct Exit;
 
+// an exit that can't be used until every boss on the stage is dead
+#[derive(Component)]
+pub struct LockedExit;
+
+// who fired a bullet, it only hurts the other side
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Team {
+    Player,
+    Enemy,
+}
+
 #[derive(Component)]
 pub struct Bullet {
-    pub move_vector: Vec3,
+    pub team: Team,
+    // world units per second
+    pub velocity: Vec2,
+    pub damage: f32,
+    // how many more walls it can bounce off before it's used up
+    pub bounces: u32,
+    // how many more targets it can go through, and the ones it already has
+    pub piercing: u32,
+    pub hits: Vec<Entity>,
+    pub lifetime: Timer,
+    // how much further it can travel
+    pub range: f32,
 }
 
 #[derive(Component)]
 pub struct Ammo;
 
+#[derive(Component)]
+pub struct WeaponPickup {
+    pub weapon: String,
+}
+
 #[derive(Component)]
 pub struct AmmoText;
 
+#[derive(Component)]
+pub struct WeaponText;
+
 #[derive(Component)]
 pub struct HealthText;
+
+#[derive(Component)]
+pub struct SeedText;
+
+#[derive(Component)]
+pub struct GameOverFlag;
+
+#[derive(Component)]
+pub struct PauseMenuFlag;
+
+#[derive(Component)]
+pub struct SettingsMenuFlag;
+
+// the size a piece of ui text was made at, before the ui scale setting is applied
+#[derive(Component)]
+pub struct UiText {
+    pub font_size: f32,
+}
+
+// anything that can be hurt, health only ever changes through a DamageEvent
+#[derive(Component)]
+pub struct LivingEntity {
+    pub health: f32,
+    pub max_health: f32,
+    // incoming damage is scaled by this, under 1 is armour and over 1 a weak spot
+    pub damage_multiplier: f32,
+    // how long hits are ignored for after one lands, 0 for enemies that can be hit every frame
+    pub invulnerable_time: f32,
+    pub invulnerable: Option<Timer>,
+}
+
+impl LivingEntity {
+    pub fn new(health: f32) -> LivingEntity {
+        LivingEntity {
+            health,
+            max_health: health,
+            damage_multiplier: 1.0,
+            invulnerable_time: 0.0,
+            invulnerable: None,
+        }
+    }
+
+    pub fn with_invulnerable_time(mut self, seconds: f32) -> LivingEntity {
+        self.invulnerable_time = seconds;
+        self
+    }
+
+    pub fn is_invulnerable(&self) -> bool {
+        self.invulnerable.is_some()
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.health <= 0.0
+    }
+}
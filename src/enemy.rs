@@ -1,142 +1,278 @@
 use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng};
 
 use crate::{
-    components::{Enemy, EnemyFlock, Node, NodeGraph, Player, TileCollider},
+    ai::{AiBrain, AiState},
+    ascii::{spawn_ascii_sprite, AsciiSheet},
+    bullet::BulletPool,
+    camera::ScreenShake,
+    colourscheme::ColourScheme,
+    components::{
+        Ammo, Boss, Bullet, Enemy, EnemyFlock, LivingEntity, Loot, NavPath, NodeGraph, Player,
+        ShooterEnemy, Team, WeaponPickup,
+    },
+    enemydef::{LootDrop, LootItem},
+    health::{DamageEvent, DeathEvent},
     player::wall_collision_check,
-    TILE_SIZE,
+    run::RunState,
+    seed::StageRng,
+    tilegrid::TileGrid,
+    GameState, TILE_SIZE,
 };
 
+const ENEMY_CONTACT_KNOCKBACK: f32 = 300.0;
+// seconds a shooter stands still winding up before the shot comes out
+const SHOOTER_WIND_UP: f32 = 0.5;
+const SHOOTER_WIND_UP_SCALE: f32 = 0.4;
+// seconds between a shooter changing which way it strafes
+const SHOOTER_STRAFE_TIME: f32 = 1.5;
+// how far inside or outside its range a shooter is happy to be
+const SHOOTER_RANGE_SLACK: f32 = TILE_SIZE * 1.5;
+
 pub struct EnemyPlugin;
 
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(enemy_detect)
-            .add_system(enemy_chase)
-            .add_system(enemy_phys_update)
-            .add_system(enemy_hit_detect)
-            .add_system(enemy_separation);
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(enemy_vision)
+                .with_system(enemy_steering)
+                .with_system(shooter_movement)
+                .with_system(shooter_fire)
+                .with_system(enemy_phys_update)
+                .with_system(enemy_hit_detect)
+                .with_system(enemy_death),
+        );
     }
 }
 
-fn enemy_detect(
-    mut enemy_query: Query<(&mut Enemy, &Transform), With<Enemy>>,
-    player_query: Query<&Transform, (With<Player>, Without<Enemy>)>,
-) {
-    let player_transform = player_query.single();
-    for (mut enemy, enemy_transform) in enemy_query.iter_mut() {
-        let distance = Vec3::distance(enemy_transform.translation, player_transform.translation);
-        if distance < enemy.vision {
-            enemy.spotted_player = true;
+impl ShooterEnemy {
+    pub fn new(range: f32, fire_interval: f32, bullet_speed: f32, damage: f32) -> ShooterEnemy {
+        ShooterEnemy {
+            range,
+            strafe: 1.0,
+            strafe_timer: Timer::from_seconds(SHOOTER_STRAFE_TIME, true),
+            fire_timer: Timer::from_seconds(fire_interval, false),
+            wind_up: None,
+            bullet_speed,
+            damage,
         }
     }
+
+    // while it's winding up or can see the player it's after, it moves itself
+    pub fn is_engaging(&self, enemy: &Enemy, brain: &AiBrain) -> bool {
+        self.wind_up.is_some() || (brain.state == AiState::Chase && enemy.sees_player)
+    }
 }
 
-fn enemy_chase(
-    mut enemy_query: Query<(&Enemy, &mut EnemyFlock, &mut Transform), With<Enemy>>,
-    player_query: Query<&Transform, (Without<Enemy>, With<Player>)>,
-    tile_query: Query<&Transform, (Without<Player>, Without<Enemy>, With<TileCollider>)>,
+// heads for wherever the enemy's AiBrain wants it to go, or slows to a stop if nowhere
+fn enemy_steering(
+    mut enemy_query: Query<(
+        &Enemy,
+        &AiBrain,
+        Option<&ShooterEnemy>,
+        &mut EnemyFlock,
+        &mut NavPath,
+        &Transform,
+    )>,
+    grid: Res<TileGrid>,
 ) {
-    let player_transform = player_query.single();
-
-    for (enemy, mut enemy_flock, enemy_transform) in enemy_query.iter_mut() {
-        if enemy.spotted_player {
-            let main_impulse = enemy_seek(
-                &mut enemy_flock,
-                enemy_transform.translation,
-                player_transform.translation,
-            );
-
-            let mut near_tiles: Vec<&Transform> = Vec::new();
-            for tile_transform in tile_query.iter() {
-                if Vec3::distance(enemy_transform.translation, tile_transform.translation)
-                    < TILE_SIZE * 2.0
-                {
-                    near_tiles.push(&tile_transform);
-                }
+    for (enemy, brain, shooter, mut enemy_flock, mut path, enemy_transform) in
+        enemy_query.iter_mut()
+    {
+        if shooter.is_some_and(|shooter| shooter.is_engaging(enemy, brain)) {
+            continue;
+        }
+        let move_target = match enemy.move_target {
+            Some(move_target) => move_target,
+            None => {
+                let braking = (-enemy_flock.velocity).clamp_length_max(enemy_flock.max_force);
+                enemy_flock.acceleration += braking;
+                continue;
             }
+        };
 
-            if near_tiles.len() > 0 {
-                let tile_avoid_steering: Vec3 =
-                    enemy_flee(&mut enemy_flock, enemy_transform.translation, near_tiles);
-                enemy_flock.acceleration += tile_avoid_steering;
-            }
-            enemy_flock.acceleration += main_impulse;
+        // follow the planned path around walls, head straight in once it runs out
+        let target = path
+            .next_waypoint(enemy_transform.translation)
+            .unwrap_or(move_target);
+        let main_impulse = enemy_flock.steer((target - enemy_transform.translation).truncate())
+            * enemy_flock.weights.seek;
+
+        let near_tiles = grid.walls_near(enemy_transform.translation, TILE_SIZE * 2.0);
+
+        if !near_tiles.is_empty() {
+            let tile_avoid_steering: Vec3 =
+                enemy_flee(&mut enemy_flock, enemy_transform.translation, &near_tiles);
+            enemy_flock.acceleration += tile_avoid_steering;
         }
+        enemy_flock.acceleration += main_impulse.extend(0.0);
     }
 }
 
-fn enemy_seek(enemy: &mut EnemyFlock, enemy_translation: Vec3, target: Vec3) -> Vec3 {
-    let mut desired = target - enemy_translation;
-    let mut steering = desired - enemy.velocity;
-    steering = set_magnitude(steering, enemy.max_force);
-    desired = set_magnitude(desired, enemy.max_force);
-    return desired;
+// while engaging, shooters close in or back off to stay at their range and strafe round the
+// player, the rest of the time enemy_steering moves them like any other enemy
+fn shooter_movement(
+    mut shooter_query: Query<(
+        &Enemy,
+        &AiBrain,
+        &mut ShooterEnemy,
+        &mut EnemyFlock,
+        &Transform,
+    )>,
+    player_query: Query<&Transform, (With<Player>, Without<Enemy>)>,
+    grid: Res<TileGrid>,
+    time: Res<Time>,
+) {
+    let player_transform = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    for (enemy, brain, mut shooter, mut enemy_flock, transform) in shooter_query.iter_mut() {
+        shooter.strafe_timer.tick(time.delta());
+        if shooter.strafe_timer.just_finished() {
+            shooter.strafe = -shooter.strafe;
+        }
+        if !shooter.is_engaging(enemy, brain) {
+            continue;
+        }
+
+        let desired_velocity = if shooter.wind_up.is_some() {
+            // it stands still to shoot
+            Vec3::ZERO
+        } else {
+            let to_player = (player_transform.translation - transform.translation).truncate();
+            let towards = to_player.normalize_or_zero();
+            let distance = to_player.length();
+            let closing = if distance > shooter.range + SHOOTER_RANGE_SLACK {
+                1.0
+            } else if distance < shooter.range - SHOOTER_RANGE_SLACK {
+                -1.0
+            } else {
+                0.0
+            };
+            (towards * closing + towards.perp() * shooter.strafe)
+                .normalize_or_zero()
+                .extend(0.0)
+                * enemy_flock.speed
+        };
+
+        let steering =
+            (desired_velocity - enemy_flock.velocity).clamp_length_max(enemy_flock.max_force);
+        enemy_flock.acceleration += steering;
+
+        let near_tiles = grid.walls_near(transform.translation, TILE_SIZE * 2.0);
+        if !near_tiles.is_empty() {
+            let tile_avoid_steering =
+                enemy_flee(&mut enemy_flock, transform.translation, &near_tiles);
+            enemy_flock.acceleration += tile_avoid_steering;
+        }
+    }
 }
 
-fn enemy_flee(
-    enemy: &mut EnemyFlock,
-    enemy_translation: Vec3,
-    avoid_list: Vec<&Transform>,
-) -> Vec3 {
+// the aim is locked in when the wind up starts, so moving during it dodges the shot
+fn shooter_fire(
+    mut commands: Commands,
+    mut bullet_pool: ResMut<BulletPool>,
+    mut shooter_query: Query<(&Enemy, &AiBrain, &mut ShooterEnemy, &mut Transform)>,
+    player_query: Query<&Transform, (With<Player>, Without<Enemy>)>,
+    time: Res<Time>,
+) {
+    let player_transform = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    for (enemy, brain, mut shooter, mut transform) in shooter_query.iter_mut() {
+        shooter.fire_timer.tick(time.delta());
+
+        let mut fire_direction = None;
+        if let Some((wind_up, direction)) = shooter.wind_up.as_mut() {
+            wind_up.tick(time.delta());
+            // it swells up while winding up so the shot can be seen coming
+            transform.scale = Vec3::splat(1.0 + SHOOTER_WIND_UP_SCALE * wind_up.percent());
+            if wind_up.finished() {
+                fire_direction = Some(*direction);
+            }
+        } else if shooter.is_engaging(enemy, brain) && shooter.fire_timer.finished() {
+            let direction = (player_transform.translation - transform.translation)
+                .truncate()
+                .normalize_or_zero();
+            shooter.wind_up = Some((Timer::from_seconds(SHOOTER_WIND_UP, false), direction));
+        }
+
+        if let Some(direction) = fire_direction {
+            let bullet = Bullet {
+                damage: shooter.damage,
+                ..Bullet::new(Team::Enemy, direction * shooter.bullet_speed)
+            };
+            bullet_pool.fire(&mut commands, transform.translation, bullet);
+            transform.scale = Vec3::ONE;
+            shooter.wind_up = None;
+            shooter.fire_timer.reset();
+        }
+    }
+}
+
+fn enemy_flee(enemy: &mut EnemyFlock, enemy_translation: Vec3, avoid_list: &[Vec3]) -> Vec3 {
     let mut steering = Vec3::splat(0.0);
-    if avoid_list.len() > 0 {
+    if !avoid_list.is_empty() {
         for avoid in avoid_list.iter() {
-            let d = Vec3::distance(avoid.translation, enemy_translation);
-            let mut diff = get_vector(enemy_translation, avoid.translation);
-            diff = diff / (d * d);
+            let d = Vec3::distance(*avoid, enemy_translation);
+            let mut diff = get_vector(enemy_translation, *avoid);
+            diff /= d * d;
             steering += diff;
         }
-        steering = steering / (avoid_list.len() as f32).round();
+        steering /= (avoid_list.len() as f32).round();
         steering = set_magnitude(steering, enemy.max_force);
         // steering = steering - enemy.velocity; // steering formula
-        steering = Vec3::clamp_length_max(steering, enemy.speed * 2.5);
-        return steering;
+        Vec3::clamp_length_max(steering, enemy.speed * 2.5)
     } else {
-        return Vec3::splat(0.0);
+        Vec3::splat(0.0)
     }
 }
 
-fn enemy_vision(
-    mut enemy_query: Query<(&mut Transform, &Enemy, &mut EnemyFlock), With<Enemy>>,
-) -> Vec<Transform> {
-    return Vec::new();
-}
-
-fn enemy_separation(
-    mut enemy_query: Query<(&Transform, &Enemy, &mut EnemyFlock), With<EnemyFlock>>,
+// works out whether every enemy can see the player this frame: in range, inside the view cone
+// and not hidden behind walls
+pub fn enemy_vision(
+    graph: Res<NodeGraph>,
+    mut enemy_query: Query<(&Transform, &mut Enemy)>,
+    player_query: Query<&Transform, (With<Player>, Without<Enemy>)>,
 ) {
-    let mut iter = enemy_query.iter_combinations_mut();
-    while let Some([(transform, enemy, mut enemy_flock), (transform2, enemy2, enemy_flock2)]) =
-        iter.fetch_next()
-    {
-        let mut something_in_vision = false;
-        let mut vision_total = 0;
-        let mut steering = Vec3::splat(0.0);
-        if Vec3::distance(transform.translation, transform2.translation) < enemy.vision / 2.0 {
-            something_in_vision = true;
-            let d = Vec3::distance(transform.translation, transform2.translation);
-            let mut diff = get_vector(transform.translation, transform2.translation);
-            diff = diff / (d * d);
-            steering += diff;
+    let player_transform = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
 
-            vision_total += 1;
-        }
+    for (transform, mut enemy) in enemy_query.iter_mut() {
+        enemy.sees_player = can_see(
+            &graph,
+            &enemy,
+            transform.translation,
+            player_transform.translation,
+        );
+    }
+}
 
-        if something_in_vision {
-            // this is to avoid dividing by zero and making things go wonky
-            // apply forces
-            steering = steering / vision_total as f32;
-            steering = set_magnitude(steering, enemy_flock.max_force * 0.5);
-            steering = Vec3::clamp_length_max(steering, enemy_flock.speed);
-            enemy_flock.acceleration += steering;
+fn can_see(graph: &NodeGraph, enemy: &Enemy, from: Vec3, target: Vec3) -> bool {
+    let to_target = (target - from).truncate();
+    if to_target.length() > enemy.vision {
+        return false;
+    }
+    if let Some(half_angle) = enemy.view_cone {
+        // angle_between is signed, anything clockwise of facing comes out negative
+        if to_target.length() > 0.0
+            && enemy.facing.truncate().angle_between(to_target).abs() > half_angle
+        {
+            return false;
         }
     }
+    graph.line_of_sight(from, target)
 }
 
-pub fn set_magnitude(mut vector: Vec3, magnitude: f32) -> Vec3 {
-    vector = vector / vector.length();
-    vector *= magnitude;
-    return vector;
+pub fn set_magnitude(vector: Vec3, magnitude: f32) -> Vec3 {
+    vector / vector.length() * magnitude
 }
 
 fn get_vector(vec_1: Vec3, vec_2: Vec3) -> Vec3 {
@@ -144,47 +280,209 @@ fn get_vector(vec_1: Vec3, vec_2: Vec3) -> Vec3 {
 }
 
 fn enemy_phys_update(
-    mut enemy_query: Query<(&mut Transform, &mut EnemyFlock, &Enemy), With<EnemyFlock>>,
-    tile_query: Query<
-        (&Transform, &TileCollider),
-        (With<TileCollider>, Without<Player>, Without<EnemyFlock>),
+    mut enemy_query: Query<
+        (&mut Transform, &mut EnemyFlock, &mut Enemy, &AiBrain),
+        With<EnemyFlock>,
     >,
+    grid: Res<TileGrid>,
     time: Res<Time>,
 ) {
-    for (mut transform, mut enemy_flock, enemy) in enemy_query.iter_mut() {
-        if enemy.spotted_player {
-            enemy_flock.velocity = enemy_flock.velocity + enemy_flock.acceleration;
-            enemy_flock.velocity = Vec3::clamp_length_max(enemy_flock.velocity, enemy_flock.speed);
-            enemy_flock.velocity[2] = 0.0;
-
-            let wish_pos = Vec3::new(enemy_flock.velocity[0] * time.delta_seconds(), 0.0, 0.0)
-                + transform.translation;
-            if !wall_collision_check(wish_pos, &tile_query) {
-                transform.translation = wish_pos;
-            }
+    for (mut transform, mut enemy_flock, mut enemy, brain) in enemy_query.iter_mut() {
+        enemy_flock.velocity = enemy_flock.velocity + enemy_flock.acceleration;
+        enemy_flock.velocity = Vec3::clamp_length_max(
+            enemy_flock.velocity,
+            enemy_flock.speed * brain.state.speed_scale(),
+        );
+        enemy_flock.velocity[2] = 0.0;
+        if enemy_flock.velocity.length_squared() > 1.0 {
+            enemy.facing = enemy_flock.velocity.normalize();
+        }
+
+        let wish_pos = Vec3::new(enemy_flock.velocity[0] * time.delta_seconds(), 0.0, 0.0)
+            + transform.translation;
+        if !wall_collision_check(wish_pos, &grid) {
+            transform.translation = wish_pos;
+        }
+
+        let wish_pos = Vec3::new(0.0, enemy_flock.velocity[1] * time.delta_seconds(), 0.0)
+            + transform.translation;
+        if !wall_collision_check(wish_pos, &grid) {
+            transform.translation = wish_pos;
+        }
+
+        enemy_flock.acceleration = Vec3::splat(0.0);
+        transform.translation[2] = 0.0;
+    }
+}
 
-            let wish_pos = Vec3::new(0.0, enemy_flock.velocity[1] * time.delta_seconds(), 0.0)
-                + transform.translation;
-            if !wall_collision_check(wish_pos, &tile_query) {
-                transform.translation = wish_pos;
+fn enemy_death(
+    mut commands: Commands,
+    mut death_events: EventReader<DeathEvent>,
+    enemy_query: Query<(&Transform, Option<&Loot>), With<Enemy>>,
+    ascii: Res<AsciiSheet>,
+    colours: Res<ColourScheme>,
+    mut run: ResMut<RunState>,
+    mut rng: ResMut<StageRng>,
+) {
+    for death in death_events.iter() {
+        if let Ok((transform, loot)) = enemy_query.get(death.entity) {
+            commands.entity(death.entity).despawn();
+            run.add_kill();
+            if let Some(Loot(drops)) = loot {
+                drop_loot(
+                    &mut commands,
+                    &ascii,
+                    &colours,
+                    drops,
+                    transform.translation,
+                    &mut rng.0,
+                );
             }
+        }
+    }
+}
 
-            enemy_flock.acceleration = Vec3::splat(0.0);
-            transform.translation[2] = 0.0;
+// every drop is rolled on its own, so one enemy can leave several things behind
+fn drop_loot(
+    commands: &mut Commands,
+    ascii: &AsciiSheet,
+    colours: &ColourScheme,
+    drops: &[LootDrop],
+    position: Vec3,
+    rng: &mut StdRng,
+) {
+    for drop in drops.iter() {
+        if !rng.gen_bool(drop.chance) {
+            continue;
+        }
+        let item = spawn_ascii_sprite(
+            commands,
+            ascii,
+            drop.glyph,
+            drop.colour.resolve(colours, false),
+            position,
+            Vec2::splat(TILE_SIZE),
+        );
+        match &drop.item {
+            LootItem::Ammo => {
+                commands.entity(item).insert(Ammo);
+            }
+            LootItem::Weapon(weapon) => {
+                commands.entity(item).insert(WeaponPickup {
+                    weapon: weapon.clone(),
+                });
+            }
         }
     }
 }
 
+// only the enemies with contact damage throw themselves at the player, shooters keep their
+// distance. bosses hit the player without being used up
 fn enemy_hit_detect(
     mut commands: Commands,
-    enemy_query: Query<(Entity, &Transform), With<Enemy>>,
-    mut player_query: Query<(&mut Player, &Transform), With<Player>>,
+    enemy_query: Query<(Entity, &Transform, &Enemy, Option<&Boss>)>,
+    player_query: Query<(Entity, &Transform, &LivingEntity), With<Player>>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut shake_events: EventWriter<ScreenShake>,
 ) {
-    let (mut player, player_transform) = player_query.single_mut();
-    for (enemy, enemy_transform) in enemy_query.iter() {
-        if Vec3::distance(enemy_transform.translation, player_transform.translation) < TILE_SIZE {
-            commands.entity(enemy).despawn();
-            player.health -= 1;
+    let (player, player_transform, player_living) = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    // enemies that run into the player while it's flashing aren't used up
+    if player_living.is_invulnerable() {
+        return;
+    }
+    for (entity, enemy_transform, enemy, boss) in enemy_query.iter() {
+        if enemy.contact_damage > 0.0
+            && Vec3::distance(enemy_transform.translation, player_transform.translation) < TILE_SIZE
+        {
+            if boss.is_none() {
+                commands.entity(entity).despawn();
+            }
+            damage_events.send(DamageEvent {
+                source: Some(entity),
+                target: player,
+                amount: enemy.contact_damage,
+                knockback: (player_transform.translation - enemy_transform.translation)
+                    .truncate()
+                    .normalize_or_zero()
+                    * ENEMY_CONTACT_KNOCKBACK,
+            });
+            shake_events.send(ScreenShake { trauma: 0.5 });
+            // only one hit gets through before the invulnerability starts
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_3;
+
+    use super::*;
+    use crate::tilegrid::{tile_translation, TILE_STEP};
+
+    const CENTRE: IVec2 = IVec2::new(10, 10);
+
+    // an empty room 21 tiles across with a wall tile three to the left of the centre
+    fn room() -> NodeGraph {
+        let mut walls = Vec::new();
+        for i in 0..=20 {
+            walls.extend([
+                IVec2::new(i, 0),
+                IVec2::new(i, 20),
+                IVec2::new(0, i),
+                IVec2::new(20, i),
+            ]);
+        }
+        walls.push(CENTRE - IVec2::new(3, 0));
+        NodeGraph::from_grid(&TileGrid::new(&walls, Vec::new()))
+    }
+
+    // looking up with a runner's 120 degree cone
+    fn runner() -> Enemy {
+        Enemy {
+            vision: TILE_STEP * 8.0,
+            facing: Vec3::Y,
+            view_cone: Some(FRAC_PI_3),
+            sees_player: false,
+            last_known_position: None,
+            move_target: None,
+            contact_damage: 1.0,
         }
     }
+
+    fn sees(enemy: &Enemy, offset: IVec2) -> bool {
+        let from = tile_translation(CENTRE);
+        can_see(&room(), enemy, from, tile_translation(CENTRE + offset))
+    }
+
+    #[test]
+    fn sees_inside_the_cone_on_both_sides() {
+        let enemy = runner();
+        assert!(sees(&enemy, IVec2::new(0, 5)));
+        assert!(sees(&enemy, IVec2::new(2, 4)));
+        assert!(sees(&enemy, IVec2::new(-2, 4)));
+    }
+
+    #[test]
+    fn blind_outside_the_cone_on_both_sides() {
+        let enemy = runner();
+        assert!(!sees(&enemy, IVec2::new(4, 0)));
+        assert!(!sees(&enemy, IVec2::new(4, -1)));
+        assert!(!sees(&enemy, IVec2::new(-2, -4)));
+        assert!(!sees(&enemy, IVec2::new(0, -5)));
+    }
+
+    #[test]
+    fn range_and_walls_still_count() {
+        let enemy = Enemy {
+            view_cone: None,
+            ..runner()
+        };
+        assert!(sees(&enemy, IVec2::new(0, -5)));
+        assert!(!sees(&enemy, IVec2::new(0, 9)));
+        assert!(!sees(&enemy, IVec2::new(-5, 0)));
+    }
 }
@@ -0,0 +1,313 @@
+use std::collections::HashSet;
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadState, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use crate::{
+    flocking::FlockWeights,
+    legend::{ColourSlot, SpawnChance},
+    StageEvent,
+};
+
+// the ascii sprite sheet is 16 by 16
+const GLYPH_COUNT: usize = 256;
+
+pub struct EnemyDefPlugin;
+
+impl Plugin for EnemyDefPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<EnemyFile>()
+            .init_asset_loader::<EnemyLoader>()
+            .add_startup_system(load_enemies)
+            .add_system(build_enemy_library);
+    }
+}
+
+// one entry of assets/enemies/all.enemies.ron
+#[derive(Deserialize, Clone)]
+pub struct EnemyDef {
+    pub name: String,
+    pub glyph: usize,
+    pub colour: ColourSlot,
+    pub health: f32,
+    // tiles
+    pub vision: f32,
+    // full width in degrees, None sees all the way round
+    #[serde(default)]
+    pub view_cone: Option<f32>,
+    pub speed: f32,
+    pub max_force: f32,
+    pub behaviour: Behaviour,
+    // what running into the player does, 0 never touches them
+    #[serde(default)]
+    pub contact_damage: f32,
+    // fraction of health at or below which it runs, 0 never runs
+    #[serde(default)]
+    pub flee_health: f32,
+    #[serde(default)]
+    pub flocking: FlockWeights,
+    #[serde(default)]
+    pub loot: Vec<LootDrop>,
+    #[serde(default)]
+    pub spawn_chance: SpawnChance,
+    // the first stage it can turn up on, the first stage of a run is 1
+    #[serde(default)]
+    pub min_stage: i32,
+}
+
+#[derive(Deserialize, Clone)]
+pub enum Behaviour {
+    // chases the player down and runs into them
+    Runner,
+    // keeps range tiles away and fires every fire_interval seconds
+    Shooter {
+        range: f32,
+        fire_interval: f32,
+        bullet_speed: f32,
+        damage: f32,
+    },
+    // fires bullet patterns at the player, moving on to the next phase as it loses health.
+    // the exit of a boss stage stays locked until it's dead
+    Boss {
+        phases: Vec<BossPhase>,
+    },
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BossPhase {
+    // the phase starts once the boss is down to this fraction of its health
+    pub health: f32,
+    // every pattern of the phase fires on its own timer
+    pub patterns: Vec<BulletPattern>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct BulletPattern {
+    pub shape: PatternShape,
+    // seconds between volleys
+    pub interval: f32,
+    pub bullet_speed: f32,
+    pub damage: f32,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum PatternShape {
+    // bullets spaced evenly all the way round, turned by spin degrees every volley
+    Ring { bullets: u32, spin: f32 },
+    // bullets fanned evenly across spread degrees, aimed at the player
+    Fan { bullets: u32, spread: f32 },
+}
+
+// something that might be left behind when the enemy dies, each drop is rolled on its own
+#[derive(Deserialize, Clone)]
+pub struct LootDrop {
+    pub item: LootItem,
+    pub chance: f64,
+    pub glyph: usize,
+    pub colour: ColourSlot,
+}
+
+#[derive(Deserialize, Clone)]
+pub enum LootItem {
+    Ammo,
+    // the name of a weapon in assets/weapons/all.weapons.ron
+    Weapon(String),
+}
+
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "9b1f3c52-7e0a-4d8b-b6e2-2a4c8d71f0e3"]
+#[serde(transparent)]
+pub struct EnemyFile(Vec<EnemyDef>);
+
+impl EnemyFile {
+    // catches the numbers that would parse but break the game, like an enemy with no health
+    pub fn validate(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for enemy in self.0.iter() {
+            let fail = |message: &str| Err(format!("enemy \"{}\" {}", enemy.name, message));
+            if enemy.name.is_empty() {
+                return Err("an enemy has no name".to_string());
+            }
+            if !names.insert(enemy.name.as_str()) {
+                return fail("is defined more than once");
+            }
+            if enemy.glyph >= GLYPH_COUNT {
+                return fail("has a glyph past the end of the sprite sheet");
+            }
+            if enemy.health <= 0.0 || enemy.vision <= 0.0 {
+                return fail("needs more than 0 health and vision");
+            }
+            if enemy.speed <= 0.0 || enemy.max_force <= 0.0 {
+                return fail("needs more than 0 speed and max_force");
+            }
+            if matches!(enemy.view_cone, Some(cone) if cone <= 0.0 || cone > 360.0) {
+                return fail("has a view_cone outside 0 to 360 degrees");
+            }
+            if enemy.contact_damage < 0.0 {
+                return fail("has negative contact_damage");
+            }
+            if !(0.0..1.0).contains(&enemy.flee_health) {
+                return fail("has a flee_health outside 0 to 1");
+            }
+            match &enemy.behaviour {
+                Behaviour::Runner => {}
+                Behaviour::Shooter {
+                    range,
+                    fire_interval,
+                    bullet_speed,
+                    damage,
+                } => {
+                    if *range <= 0.0
+                        || *fire_interval <= 0.0
+                        || *bullet_speed <= 0.0
+                        || *damage <= 0.0
+                    {
+                        return fail(
+                            "needs more than 0 range, fire_interval, bullet_speed and damage",
+                        );
+                    }
+                }
+                Behaviour::Boss { phases } => {
+                    if let Err(message) = validate_phases(phases) {
+                        return fail(message);
+                    }
+                }
+            }
+            for drop in enemy.loot.iter() {
+                if !(0.0..=1.0).contains(&drop.chance) {
+                    return fail("has a loot chance outside 0 to 1");
+                }
+                if drop.glyph >= GLYPH_COUNT {
+                    return fail("has loot with a glyph past the end of the sprite sheet");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// the first phase has to start at full health and each one after it lower down, so there's
+// always exactly one phase for any amount of health
+fn validate_phases(phases: &[BossPhase]) -> Result<(), &'static str> {
+    match phases.first() {
+        None => return Err("is a boss with no phases"),
+        Some(first) if first.health != 1.0 => {
+            return Err("has a first phase that isn't at 1 health")
+        }
+        _ => {}
+    }
+    if phases
+        .windows(2)
+        .any(|pair| pair[1].health >= pair[0].health)
+    {
+        return Err("has phases that aren't in order of falling health");
+    }
+    for phase in phases.iter() {
+        if phase.health <= 0.0 {
+            return Err("has a phase at 0 health or less");
+        }
+        for pattern in phase.patterns.iter() {
+            if pattern.interval <= 0.0 || pattern.bullet_speed <= 0.0 || pattern.damage <= 0.0 {
+                return Err(
+                    "has a pattern that needs more than 0 interval, bullet_speed and damage",
+                );
+            }
+            let bullets = match pattern.shape {
+                PatternShape::Ring { bullets, .. } | PatternShape::Fan { bullets, .. } => bullets,
+            };
+            if bullets == 0 {
+                return Err("has a pattern with no bullets");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct EnemyLoader;
+
+impl AssetLoader for EnemyLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let enemies: EnemyFile = ron::de::from_bytes(bytes)?;
+            enemies.validate().map_err(bevy::asset::Error::msg)?;
+            load_context.set_default_asset(LoadedAsset::new(enemies));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["enemies.ron"]
+    }
+}
+
+struct EnemyFileHandle(Handle<EnemyFile>);
+
+// every enemy by name, made once the file has loaded and again whenever it's edited
+pub struct EnemyLibrary {
+    enemies: Vec<EnemyDef>,
+}
+
+impl EnemyLibrary {
+    pub fn get(&self, name: &str) -> Option<&EnemyDef> {
+        self.enemies.iter().find(|enemy| enemy.name == name)
+    }
+}
+
+fn load_enemies(mut commands: Commands, assets: Res<AssetServer>) {
+    commands.insert_resource(EnemyFileHandle(assets.load("enemies/all.enemies.ron")));
+}
+
+fn build_enemy_library(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    handle: Res<EnemyFileHandle>,
+    enemy_files: Res<Assets<EnemyFile>>,
+    mut enemy_events: EventReader<AssetEvent<EnemyFile>>,
+    library: Option<Res<EnemyLibrary>>,
+    mut stage_events: EventWriter<StageEvent>,
+) {
+    let modified = enemy_events.iter().count() > 0;
+    if library.is_some() && !modified {
+        return;
+    }
+    match assets.get_load_state(&handle.0) {
+        LoadState::Loaded => {}
+        // the reason has already been logged by the asset server, an edit that doesn't parse
+        // or validate keeps the enemies there were before
+        LoadState::Failed if library.is_none() => panic!("Couldn't load the enemies."),
+        _ => return,
+    }
+    if let Some(enemy_file) = enemy_files.get(&handle.0) {
+        commands.insert_resource(EnemyLibrary {
+            enemies: enemy_file.0.clone(),
+        });
+        // enemies are only made with the stage, so the stage is made again to show the edit
+        if library.is_some() {
+            println!("enemies changed, regenerating the stage");
+            stage_events.send(StageEvent::Reload);
+        }
+    }
+}
+
+// assets/enemies/all.enemies.ron read straight off the disk since tests have no asset server
+#[cfg(test)]
+pub fn test_library() -> EnemyLibrary {
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/assets/enemies/all.enemies.ron"
+    );
+    let contents = std::fs::read_to_string(path).expect("Couldn't read the enemies.");
+    let enemies: EnemyFile = ron::from_str(&contents).unwrap();
+    enemies.validate().unwrap();
+    EnemyLibrary { enemies: enemies.0 }
+}
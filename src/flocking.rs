@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    components::{Enemy, EnemyFlock},
+    GameState, TILE_SIZE,
+};
+
+// enemies further apart than this don't flock together, it's also the size of a grid cell so
+// every neighbour is in the 3x3 cells round an enemy
+const NEIGHBOUR_RADIUS: f32 = TILE_SIZE * 4.0;
+// enemies closer than this push apart
+const SEPARATION_RADIUS: f32 = TILE_SIZE * 1.5;
+
+pub struct FlockingPlugin;
+
+impl Plugin for FlockingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_update(GameState::Playing).with_system(flock));
+    }
+}
+
+// how much each steering force counts for, from the flocking field of each enemy in
+// assets/enemies/all.enemies.ron
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct FlockWeights {
+    // heading for wherever the enemy's AiBrain wants it to go
+    pub seek: f32,
+    // keeping out of each other's way
+    pub separation: f32,
+    // going the same way as the rest of the pack
+    pub alignment: f32,
+    // staying with the rest of the pack
+    pub cohesion: f32,
+}
+
+impl Default for FlockWeights {
+    fn default() -> Self {
+        FlockWeights {
+            seek: 1.0,
+            separation: 1.5,
+            alignment: 1.0,
+            cohesion: 1.0,
+        }
+    }
+}
+
+impl EnemyFlock {
+    // reynolds steering, the change in velocity that would have it going flat out in
+    // direction, limited to max_force
+    pub fn steer(&self, direction: Vec2) -> Vec2 {
+        let desired = direction.normalize_or_zero() * self.speed;
+        (desired - self.velocity.truncate()).clamp_length_max(self.max_force)
+    }
+}
+
+// buckets positions into square cells so neighbours are only looked for in the cells round a
+// point instead of against everything else
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<usize>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32, positions: impl Iterator<Item = Vec2>) -> SpatialGrid {
+        let mut grid = SpatialGrid {
+            cell_size,
+            cells: HashMap::new(),
+        };
+        for (index, position) in positions.enumerate() {
+            let cell = grid.cell(position);
+            grid.cells.entry(cell).or_default().push(index);
+        }
+        grid
+    }
+
+    fn cell(&self, position: Vec2) -> IVec2 {
+        (position / self.cell_size).floor().as_ivec2()
+    }
+
+    // the index of everything in the cells touching position's cell, callers still have to
+    // check the distance
+    pub fn nearby(&self, position: Vec2) -> impl Iterator<Item = usize> + '_ {
+        let centre = self.cell(position);
+        (-1..=1)
+            .flat_map(move |y| (-1..=1).map(move |x| centre + IVec2::new(x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+    }
+}
+
+// separation, alignment and cohesion. seeking is done in enemy_steering with the path it's
+// following
+fn flock(mut flock_query: Query<(Entity, &Transform, &Enemy, &mut EnemyFlock)>) {
+    let boids: Vec<(Entity, Vec2, Vec2)> = flock_query
+        .iter()
+        .map(|(entity, transform, _, enemy_flock)| {
+            (
+                entity,
+                transform.translation.truncate(),
+                enemy_flock.velocity.truncate(),
+            )
+        })
+        .collect();
+    let grid = SpatialGrid::new(
+        NEIGHBOUR_RADIUS,
+        boids.iter().map(|(_, position, _)| *position),
+    );
+
+    for (entity, transform, enemy, mut enemy_flock) in flock_query.iter_mut() {
+        // one standing still has nothing to keep up with
+        if enemy.move_target.is_none() {
+            continue;
+        }
+        let position = transform.translation.truncate();
+        let mut separation = Vec2::ZERO;
+        let mut velocity_total = Vec2::ZERO;
+        let mut position_total = Vec2::ZERO;
+        let mut neighbours = 0;
+        for index in grid.nearby(position) {
+            let (other, other_position, other_velocity) = boids[index];
+            let offset = position - other_position;
+            let distance = offset.length();
+            if other == entity || distance >= NEIGHBOUR_RADIUS || distance == 0.0 {
+                continue;
+            }
+            // the closer they are the harder they push
+            if distance < SEPARATION_RADIUS {
+                separation += offset / (distance * distance);
+            }
+            velocity_total += other_velocity;
+            position_total += other_position;
+            neighbours += 1;
+        }
+
+        let weights = enemy_flock.weights;
+        let mut steering = Vec2::ZERO;
+        if separation != Vec2::ZERO {
+            steering += enemy_flock.steer(separation) * weights.separation;
+        }
+        if neighbours > 0 {
+            // a pack that's stood still has no heading to line up with
+            let average_velocity = velocity_total / neighbours as f32;
+            if average_velocity != Vec2::ZERO {
+                steering += enemy_flock.steer(average_velocity) * weights.alignment;
+            }
+            let centre = position_total / neighbours as f32;
+            steering += enemy_flock.steer(centre - position) * weights.cohesion;
+        }
+        enemy_flock.acceleration += steering.extend(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nearby(grid: &SpatialGrid, position: Vec2) -> Vec<usize> {
+        let mut nearby: Vec<usize> = grid.nearby(position).collect();
+        nearby.sort();
+        nearby
+    }
+
+    #[test]
+    fn neighbours_across_cell_edges() {
+        let positions = [
+            Vec2::new(9.5, 9.5),
+            Vec2::new(10.5, 10.5),
+            Vec2::new(-0.5, 5.0),
+            Vec2::new(25.0, 5.0),
+            Vec2::new(-15.0, -5.0),
+        ];
+        let grid = SpatialGrid::new(10.0, positions.iter().copied());
+        // each side of the corner where four cells meet
+        assert_eq!(nearby(&grid, positions[0]), vec![0, 1, 2]);
+        assert_eq!(nearby(&grid, positions[1]), vec![0, 1, 3]);
+        // negative positions round down, not towards zero
+        assert_eq!(nearby(&grid, positions[2]), vec![0, 2, 4]);
+        assert_eq!(nearby(&grid, Vec2::new(-25.0, -5.0)), vec![4]);
+        assert!(nearby(&grid, Vec2::new(100.0, 100.0)).is_empty());
+    }
+}
@@ -1,71 +1,98 @@
 use bevy::prelude::*;
 
 use crate::{
+    ai::AiBrain,
     ascii::{spawn_ascii_sprite, AsciiSheet},
-    components::{Enemy, EnemyFlock, RunnerEnemy},
+    components::{Boss, Enemy, EnemyFlock, LivingEntity, Loot, NavPath, RunnerEnemy, ShooterEnemy},
+    enemydef::{Behaviour, EnemyDef},
+    TILE_SIZE,
 };
 
 pub struct GameObjectPlugin;
 
 impl Plugin for GameObjectPlugin {
-    fn build(&self, app: &mut App) {}
+    fn build(&self, _app: &mut App) {}
 }
 
+// the parts every enemy has, the rest depends on its behaviour
 pub fn spawn_base_enemy(
-    mut commands: &mut Commands,
+    commands: &mut Commands,
     ascii: &AsciiSheet,
-    sprite_index: usize,
+    def: &EnemyDef,
     color: Color,
     position: Vec3,
-    size: Vec2,
-    health: f32,
-    vision: f32,
+    brain: AiBrain,
 ) -> Entity {
-    let enemy = spawn_ascii_sprite(&mut commands, &ascii, sprite_index, color, position, size);
+    let enemy = spawn_ascii_sprite(
+        commands,
+        ascii,
+        def.glyph,
+        color,
+        position,
+        Vec2::splat(TILE_SIZE),
+    );
     commands
         .entity(enemy)
         .insert(Enemy {
-            health,
-            vision,
-            spotted_player: false,
+            vision: TILE_SIZE * def.vision,
+            facing: Vec3::Y,
+            view_cone: def.view_cone.map(|degrees| degrees.to_radians() / 2.0),
+            sees_player: false,
+            last_known_position: None,
+            move_target: None,
+            contact_damage: def.contact_damage,
         })
+        .insert(brain)
+        .insert(LivingEntity::new(def.health))
+        .insert(NavPath::default())
         .insert(Name::new("Enemy"));
 
     enemy
 }
 
-pub fn spawn_runner_enemy(
-    mut commands: &mut Commands,
+// builds any enemy in assets/enemies/all.enemies.ron, the colour has already been picked
+// from the stage's colour scheme
+pub fn spawn_enemy(
+    commands: &mut Commands,
     ascii: &AsciiSheet,
-    sprite_index: usize,
+    def: &EnemyDef,
     color: Color,
     position: Vec3,
-    size: Vec2,
-    health: f32,
-    vision: f32,
-    speed: f32,
-    max_force: f32,
+    brain: AiBrain,
 ) -> Entity {
-    let enemy = spawn_base_enemy(
-        &mut commands,
-        &ascii,
-        sprite_index,
-        color,
-        position,
-        size,
-        health,
-        vision,
-    );
+    let enemy = spawn_base_enemy(commands, ascii, def, color, position, brain);
     commands
         .entity(enemy)
         .insert(EnemyFlock {
-            speed,
-            max_force,
+            speed: def.speed,
+            max_force: def.max_force,
             velocity: Vec3::splat(0.0),
             acceleration: Vec3::splat(0.0),
-            in_view: Vec::new(),
+            weights: def.flocking,
         })
-        .insert(RunnerEnemy);
+        .insert(Loot(def.loot.clone()))
+        .insert(Name::new(def.name.clone()));
+    match &def.behaviour {
+        Behaviour::Runner => {
+            commands.entity(enemy).insert(RunnerEnemy);
+        }
+        Behaviour::Shooter {
+            range,
+            fire_interval,
+            bullet_speed,
+            damage,
+        } => {
+            commands.entity(enemy).insert(ShooterEnemy::new(
+                TILE_SIZE * range,
+                *fire_interval,
+                *bullet_speed,
+                *damage,
+            ));
+        }
+        Behaviour::Boss { phases } => {
+            commands.entity(enemy).insert(Boss::new(phases.clone()));
+        }
+    }
 
     enemy
 }
@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+
+use crate::{
+    actions::Action, colourscheme::ColourScheme, components::GameOverFlag, run::RunState,
+    seed::new_run_seed, spawn_camera, ui::make_text_bundle, GameState, StageEvent,
+};
+
+pub struct GameOverPlugin;
+
+impl Plugin for GameOverPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::GameEnd).with_system(game_over_screen_setup),
+        )
+        .add_system_set(SystemSet::on_update(GameState::GameEnd).with_system(game_over_menu));
+    }
+}
+
+fn game_over_screen_setup(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    colours: Res<ColourScheme>,
+    run: Res<RunState>,
+) {
+    let minutes = (run.run_time / 60.0) as i32;
+    let seconds = run.run_time as i32 % 60;
+
+    // the first stage of a run is stage_number 2, it gets bumped before the stage is made
+    let lines = [
+        ("Game Over".to_string(), 60.0),
+        (format!("Stage reached: {}", run.stage_number - 1), 30.0),
+        (format!("Score: {}", run.score), 30.0),
+        (format!("Kills: {}", run.kills), 30.0),
+        (format!("Ammo used: {}", run.ammo_used), 30.0),
+        (format!("Time: {}:{:02}", minutes, seconds), 30.0),
+        (
+            "Return to play again, Escape for the title screen".to_string(),
+            20.0,
+        ),
+    ];
+    for (index, (line, font_size)) in lines.into_iter().enumerate() {
+        let text = make_text_bundle(
+            &mut commands,
+            &assets,
+            font_size,
+            line,
+            colours.colour_0,
+            Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Percent(25.0 + index as f32 * 8.0),
+                    left: Val::Percent(35.0),
+                    ..default()
+                },
+                ..default()
+            },
+        );
+        commands.entity(text).insert(GameOverFlag);
+    }
+}
+
+fn game_over_menu(
+    commands: Commands,
+    actions: Res<Input<Action>>,
+    mut state: ResMut<State<GameState>>,
+    entities_query: Query<Entity>,
+    mut run: ResMut<RunState>,
+    mut stage_events: EventWriter<StageEvent>,
+) {
+    let restart = actions.just_pressed(Action::Confirm);
+    let to_title = actions.just_pressed(Action::Cancel);
+    if !restart && !to_title {
+        return;
+    }
+
+    reset_run(&mut run);
+    if restart {
+        // make_new_stage clears away the old stage and the game over screen, then switches
+        // to Playing
+        stage_events.send(StageEvent::Next);
+    } else {
+        clear_for_title(commands, &entities_query);
+        state
+            .set(GameState::OpeningMenu)
+            .expect("Failed to change game state.");
+    }
+}
+
+// the next run starts from scratch, with a new seed unless one was passed in
+pub fn reset_run(run: &mut RunState) {
+    *run = RunState::new(new_run_seed());
+}
+
+// gets rid of the stage before going back to the title screen, which sets itself up when the
+// state changes
+pub fn clear_for_title(mut commands: Commands, entities_query: &Query<Entity>) {
+    for entity in entities_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    spawn_camera(commands);
+}
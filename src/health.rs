@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+
+use crate::{
+    components::{EnemyFlock, LivingEntity, Player},
+    GameState,
+};
+
+// how many times a second something flickers while it can't be hurt
+const INVULNERABLE_FLASH_RATE: f32 = 10.0;
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageEvent>()
+            .add_event::<DeathEvent>()
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(apply_damage)
+                    .with_system(tick_invulnerability),
+            );
+    }
+}
+
+// the one way to hurt anything with a LivingEntity. the source is whatever dealt the damage
+// and may already be despawned, like a bullet, by the time this is read
+pub struct DamageEvent {
+    pub source: Option<Entity>,
+    pub target: Entity,
+    pub amount: f32,
+    // added to the target's velocity, in units per second
+    pub knockback: Vec2,
+}
+
+// sent once when a LivingEntity's health drops to 0, whoever owns the entity cleans it up
+pub struct DeathEvent {
+    pub entity: Entity,
+}
+
+fn apply_damage(
+    mut damage_events: EventReader<DamageEvent>,
+    mut death_events: EventWriter<DeathEvent>,
+    mut living_query: Query<(
+        &mut LivingEntity,
+        Option<&mut Player>,
+        Option<&mut EnemyFlock>,
+    )>,
+) {
+    for damage in damage_events.iter() {
+        // nothing can hurt itself, so area damage can't backfire on whatever caused it
+        if damage.source == Some(damage.target) {
+            continue;
+        }
+        let (mut living, player, enemy_flock) = match living_query.get_mut(damage.target) {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        if living.is_dead() || living.is_invulnerable() {
+            continue;
+        }
+
+        living.health -= damage.amount * living.damage_multiplier;
+        if living.invulnerable_time > 0.0 {
+            living.invulnerable = Some(Timer::from_seconds(living.invulnerable_time, false));
+        }
+
+        let knockback = damage.knockback.extend(0.0);
+        if let Some(mut player) = player {
+            player.velocity += knockback;
+        }
+        if let Some(mut enemy_flock) = enemy_flock {
+            enemy_flock.velocity += knockback;
+        }
+
+        if living.is_dead() {
+            death_events.send(DeathEvent {
+                entity: damage.target,
+            });
+        }
+    }
+}
+
+fn tick_invulnerability(
+    mut living_query: Query<(&mut LivingEntity, &mut Visibility)>,
+    time: Res<Time>,
+) {
+    for (mut living, mut visibility) in living_query.iter_mut() {
+        let finished = match living.invulnerable.as_mut() {
+            Some(timer) => {
+                timer.tick(time.delta());
+                let flash = (timer.elapsed_secs() * INVULNERABLE_FLASH_RATE) as i32;
+                visibility.is_visible = flash % 2 == 0;
+                timer.finished()
+            }
+            None => continue,
+        };
+        if finished {
+            living.invulnerable = None;
+            visibility.is_visible = true;
+        }
+    }
+}
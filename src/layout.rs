@@ -0,0 +1,446 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+
+use rand::{prelude::*, rngs::StdRng};
+
+use crate::{
+    mapblock::{MapBlockData, MapBlockLibrary, Openings, MAP_BLOCK_TILES},
+    tilegrid::tile_coords,
+    wfc::WfcModel,
+};
+
+// the player spawns at the world origin, which is inside this block
+const ENTRANCE_BLOCK: IVec2 = IVec2::new(-1, -1);
+const MAX_LAYOUT_ATTEMPTS: usize = 200;
+
+// a boss stage is a short walk to the arena
+const BOSS_MAIN_PATH: usize = 2;
+
+// layouts get longer and grow more side rooms each stage until they hit these
+const MAX_MAIN_PATH: usize = 16;
+const MAX_SIDE_ROOMS: usize = 10;
+const MAX_SIDE_ROOM_LENGTH: usize = 2;
+
+const SIDES: [IVec2; 4] = [
+    IVec2::new(0, 1),
+    IVec2::new(0, -1),
+    IVec2::new(-1, 0),
+    IVec2::new(1, 0),
+];
+
+pub struct MapBlock {
+    pub x: i32,
+    pub y: i32,
+    pub block: BlockSource,
+    pub exit: bool,
+    // open edges with no block on the other side, these get walled off when the map is drawn
+    pub sealed: Openings,
+}
+
+pub enum BlockSource {
+    // index into MapBlockLibrary::blocks
+    Library(usize),
+    // made for this stage by the wfc generator
+    Generated(MapBlockData),
+}
+
+impl MapBlock {
+    pub fn data<'a>(&'a self, library: &'a MapBlockLibrary) -> &'a MapBlockData {
+        match &self.block {
+            BlockSource::Library(index) => &library.blocks[*index],
+            BlockSource::Generated(block) => block,
+        }
+    }
+}
+
+// how big a stage's layout is, in blocks
+pub struct LayoutSize {
+    // blocks walked from the entrance to the exit
+    pub main_path: usize,
+    // short dead ends branching off anywhere but the exit
+    pub side_rooms: usize,
+    // the fewest blocks the player has to go through to get to the exit, shortcuts between
+    // neighbouring blocks on the path can make it less than main_path
+    pub min_exit_distance: usize,
+}
+
+impl LayoutSize {
+    pub fn for_stage(stage_number: i32) -> LayoutSize {
+        let stage_number = stage_number.max(0) as usize;
+        let main_path = (3 + stage_number).min(MAX_MAIN_PATH);
+        LayoutSize {
+            main_path,
+            side_rooms: (stage_number / 2).min(MAX_SIDE_ROOMS),
+            min_exit_distance: (main_path * 2).div_ceil(3),
+        }
+    }
+
+    pub fn for_boss_stage() -> LayoutSize {
+        LayoutSize {
+            main_path: BOSS_MAIN_PATH,
+            side_rooms: 0,
+            min_exit_distance: BOSS_MAIN_PATH,
+        }
+    }
+}
+
+// grows a layout out from the entrance: a winding main path to the exit with side rooms off
+// it. neighbouring blocks always agree on their shared edge and the exit can always be
+// walked to. with a wfc model every block apart from the entrance and exit is generated.
+// exit_block is the library's exit, or its arena on a boss stage. None if nothing that works
+// turned up in MAX_LAYOUT_ATTEMPTS tries
+pub fn generate_layout(
+    library: &MapBlockLibrary,
+    size: &LayoutSize,
+    exit_block: usize,
+    wfc: Option<&WfcModel>,
+    rng: &mut StdRng,
+) -> Option<Vec<MapBlock>> {
+    for _ in 0..MAX_LAYOUT_ATTEMPTS {
+        if let Some(layout) = try_layout(library, size, exit_block, wfc, rng) {
+            if layout_is_solvable(library, &layout) {
+                return Some(layout);
+            }
+        }
+    }
+    None
+}
+
+// the entrance with the exit block right next to it, for when generate_layout gives up. the
+// library won't load unless this works for both the exit and the arena
+pub fn fallback_layout(library: &MapBlockLibrary, exit_block: usize) -> Option<Vec<MapBlock>> {
+    let entrance = library.blocks[library.entrance].openings();
+    let exit = library.blocks[exit_block].openings();
+    SIDES.iter().find_map(|side| {
+        if !is_open(entrance, *side) || !is_open(exit, -*side) {
+            return None;
+        }
+        let exit_cell = ENTRANCE_BLOCK + *side;
+        let placed = HashMap::from([(ENTRANCE_BLOCK, entrance), (exit_cell, exit)]);
+        let mut layout: Vec<MapBlock> =
+            [(ENTRANCE_BLOCK, library.entrance), (exit_cell, exit_block)]
+                .into_iter()
+                .map(|(cell, block)| MapBlock {
+                    x: cell.x,
+                    y: cell.y,
+                    block: BlockSource::Library(block),
+                    exit: cell == exit_cell,
+                    sealed: sealed_edges(placed[&cell], cell, &placed),
+                })
+                .collect();
+        layout.sort_by_key(|map_block| (map_block.x, map_block.y));
+        Some(layout).filter(|layout| layout_is_solvable(library, layout))
+    })
+}
+
+fn try_layout(
+    library: &MapBlockLibrary,
+    size: &LayoutSize,
+    exit_block: usize,
+    wfc: Option<&WfcModel>,
+    rng: &mut StdRng,
+) -> Option<Vec<MapBlock>> {
+    // cells in the order they were added, and the pairs of cells that have to be connected
+    let mut cells = vec![ENTRANCE_BLOCK];
+    let mut links: Vec<(IVec2, IVec2)> = Vec::new();
+
+    for _ in 0..size.main_path {
+        let from = *cells.last().unwrap();
+        let to = grow(from, &cells, rng)?;
+        cells.push(to);
+        links.push((from, to));
+    }
+    let exit = *cells.last().unwrap();
+
+    for _ in 0..size.side_rooms {
+        let branch_points: Vec<IVec2> =
+            cells.iter().copied().filter(|cell| *cell != exit).collect();
+        let mut from = *branch_points.choose(rng).unwrap();
+        for _ in 0..rng.gen_range(1..=MAX_SIDE_ROOM_LENGTH) {
+            match grow(from, &cells, rng) {
+                Some(to) => {
+                    cells.push(to);
+                    links.push((from, to));
+                    from = to;
+                }
+                None => break,
+            }
+        }
+    }
+
+    // the openings of every cell are decided before any blocks are generated, so time isn't
+    // spent generating blocks for a layout that gets thrown away
+    let mut placed: HashMap<IVec2, Openings> = HashMap::new();
+    let mut blocks: HashMap<IVec2, BlockSource> = HashMap::new();
+    for (cell, block) in [(ENTRANCE_BLOCK, library.entrance), (exit, exit_block)] {
+        placed.insert(cell, library.blocks[block].openings());
+        blocks.insert(cell, BlockSource::Library(block));
+    }
+    for cell in cells.iter() {
+        if placed.contains_key(cell) {
+            continue;
+        }
+        let required = required_openings(*cell, &links);
+        if wfc.is_some() {
+            placed.insert(*cell, generated_openings(*cell, required, &placed));
+            continue;
+        }
+        let candidates: Vec<usize> = library
+            .fillers()
+            .filter(|block| {
+                let openings = library.blocks[*block].openings();
+                includes(openings, required) && fits_neighbours(openings, *cell, &placed)
+            })
+            .collect();
+        let block = *candidates.choose(rng)?;
+        placed.insert(*cell, library.blocks[block].openings());
+        blocks.insert(*cell, BlockSource::Library(block));
+    }
+
+    if exit_distance(&placed, exit)? < size.min_exit_distance {
+        return None;
+    }
+
+    if let Some(model) = wfc {
+        for cell in cells.iter() {
+            if !blocks.contains_key(cell) {
+                let name = format!("wfc_{}_{}", cell.x, cell.y);
+                let block = model.generate_block(name, &library.legend, placed[cell], rng)?;
+                blocks.insert(*cell, BlockSource::Generated(block));
+            }
+        }
+    }
+
+    let mut layout: Vec<MapBlock> = blocks
+        .into_iter()
+        .map(|(cell, block)| MapBlock {
+            x: cell.x,
+            y: cell.y,
+            block,
+            exit: cell == exit,
+            sealed: sealed_edges(placed[&cell], cell, &placed),
+        })
+        .collect();
+    // HashMap order is random, sort so the same seed draws the blocks in the same order
+    layout.sort_by_key(|map_block| (map_block.x, map_block.y));
+    Some(layout)
+}
+
+// a random empty cell next to this one
+fn grow(from: IVec2, cells: &[IVec2], rng: &mut StdRng) -> Option<IVec2> {
+    let options: Vec<IVec2> = SIDES
+        .iter()
+        .map(|side| from + *side)
+        .filter(|cell| !cells.contains(cell))
+        .collect();
+    options.choose(rng).copied()
+}
+
+fn side_of(openings: &mut Openings, side: IVec2) -> &mut bool {
+    match (side.x, side.y) {
+        (0, 1) => &mut openings.up,
+        (0, -1) => &mut openings.down,
+        (-1, 0) => &mut openings.left,
+        _ => &mut openings.right,
+    }
+}
+
+fn is_open(mut openings: Openings, side: IVec2) -> bool {
+    *side_of(&mut openings, side)
+}
+
+fn required_openings(cell: IVec2, links: &[(IVec2, IVec2)]) -> Openings {
+    let mut required = Openings::default();
+    for (a, b) in links.iter() {
+        if *a == cell {
+            *side_of(&mut required, *b - cell) = true;
+        } else if *b == cell {
+            *side_of(&mut required, *a - cell) = true;
+        }
+    }
+    required
+}
+
+fn includes(openings: Openings, required: Openings) -> bool {
+    SIDES
+        .iter()
+        .all(|side| is_open(openings, *side) || !is_open(required, *side))
+}
+
+fn fits_neighbours(openings: Openings, cell: IVec2, placed: &HashMap<IVec2, Openings>) -> bool {
+    SIDES.iter().all(|side| match placed.get(&(cell + *side)) {
+        Some(neighbour) => is_open(openings, *side) == is_open(*neighbour, -*side),
+        None => true,
+    })
+}
+
+// generated blocks can have any openings, so they match whatever is already next to them and
+// are only open otherwise where the layout needs them to be
+fn generated_openings(
+    cell: IVec2,
+    required: Openings,
+    placed: &HashMap<IVec2, Openings>,
+) -> Openings {
+    let mut openings = required;
+    for side in SIDES.iter() {
+        if let Some(neighbour) = placed.get(&(cell + *side)) {
+            *side_of(&mut openings, *side) = is_open(*neighbour, -*side);
+        }
+    }
+    openings
+}
+
+fn sealed_edges(openings: Openings, cell: IVec2, placed: &HashMap<IVec2, Openings>) -> Openings {
+    let mut sealed = Openings::default();
+    for side in SIDES.iter() {
+        if is_open(openings, *side) && !placed.contains_key(&(cell + *side)) {
+            *side_of(&mut sealed, *side) = true;
+        }
+    }
+    sealed
+}
+
+// how many blocks apart the entrance and the exit are, going through open edges
+fn exit_distance(placed: &HashMap<IVec2, Openings>, exit: IVec2) -> Option<usize> {
+    let mut distances: HashMap<IVec2, usize> = HashMap::from([(ENTRANCE_BLOCK, 0)]);
+    let mut queue = VecDeque::from([ENTRANCE_BLOCK]);
+    while let Some(cell) = queue.pop_front() {
+        let distance = distances[&cell];
+        if cell == exit {
+            return Some(distance);
+        }
+        let openings = placed[&cell];
+        for side in SIDES.iter() {
+            let next = cell + *side;
+            if is_open(openings, *side)
+                && placed.contains_key(&next)
+                && !distances.contains_key(&next)
+            {
+                distances.insert(next, distance + 1);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+// lowest and highest block coordinates in the layout
+pub fn layout_bounds(layout: &[MapBlock]) -> (IVec2, IVec2) {
+    layout.iter().fold(
+        (IVec2::splat(i32::MAX), IVec2::splat(i32::MIN)),
+        |(min, max), block| {
+            let cell = IVec2::new(block.x, block.y);
+            (min.min(cell), max.max(cell))
+        },
+    )
+}
+
+// flood fills from the player's spawn and checks it reaches the exit tile
+pub fn layout_is_solvable(library: &MapBlockLibrary, layout: &[MapBlock]) -> bool {
+    let (min_block, max_block) = layout_bounds(layout);
+    let origin = min_block * MAP_BLOCK_TILES;
+    let size = (max_block - min_block + IVec2::ONE) * MAP_BLOCK_TILES;
+    let index = |tile: IVec2| {
+        let local = tile - origin;
+        if local.x < 0 || local.y < 0 || local.x >= size.x || local.y >= size.y {
+            None
+        } else {
+            Some((local.x * size.y + local.y) as usize)
+        }
+    };
+
+    // anything outside the layout counts as wall, so start with everything walled off. that
+    // includes the gaps in the bounding box, which get sealed when the map is drawn
+    let mut walls = vec![true; (size.x * size.y) as usize];
+    let mut exits = Vec::new();
+    for map_block in layout.iter() {
+        let block = map_block.data(library);
+        for row in 0..MAP_BLOCK_TILES {
+            for x in 0..MAP_BLOCK_TILES {
+                let tile = IVec2::new(
+                    map_block.x * MAP_BLOCK_TILES + x,
+                    map_block.y * MAP_BLOCK_TILES + MAP_BLOCK_TILES - 1 - row,
+                );
+                let char = block.tile(x, row);
+                walls[index(tile).unwrap()] = library.legend.is_wall(char);
+                if library.legend.is_exit(char) {
+                    exits.push(tile);
+                }
+            }
+        }
+    }
+
+    // gaps too narrow to walk through count as walls too
+    let is_wall = |tile: IVec2| index(tile).is_none_or(|index| walls[index]);
+    let squeezed = |tile: IVec2| {
+        (is_wall(tile + IVec2::X) && is_wall(tile - IVec2::X))
+            || (is_wall(tile + IVec2::Y) && is_wall(tile - IVec2::Y))
+    };
+    let passable = |tile: IVec2| !is_wall(tile) && !squeezed(tile);
+
+    let start = tile_coords(Vec3::ZERO);
+    if !passable(start) {
+        return false;
+    }
+    let mut visited = vec![false; walls.len()];
+    visited[index(start).unwrap()] = true;
+    let mut queue = VecDeque::from([start]);
+    while let Some(tile) = queue.pop_front() {
+        if exits.contains(&tile) {
+            return true;
+        }
+        for side in SIDES.iter() {
+            let next = tile + *side;
+            if passable(next) && !visited[index(next).unwrap()] {
+                visited[index(next).unwrap()] = true;
+                queue.push_back(next);
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapblock::test_library;
+
+    fn check_layouts(size: impl Fn(u64) -> LayoutSize, exit_block: usize, seeds: u64) {
+        let library = test_library();
+        for seed in 0..seeds {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let layout = generate_layout(&library, &size(seed), exit_block, None, &mut rng)
+                .unwrap_or_else(|| panic!("no layout for seed {}", seed));
+            assert!(layout_is_solvable(&library, &layout), "seed {}", seed);
+            assert_eq!(layout.iter().filter(|block| block.exit).count(), 1);
+        }
+    }
+
+    #[test]
+    fn stage_layouts_are_solvable() {
+        // stage 20 is past where the layouts stop growing
+        let library = test_library();
+        check_layouts(
+            |seed| LayoutSize::for_stage(seed as i32 % 20 + 1),
+            library.exit,
+            3000,
+        );
+    }
+
+    #[test]
+    fn boss_layouts_are_solvable() {
+        let library = test_library();
+        check_layouts(|_| LayoutSize::for_boss_stage(), library.arena, 200);
+    }
+
+    #[test]
+    fn fallback_layouts_are_solvable() {
+        let library = test_library();
+        for exit_block in [library.exit, library.arena] {
+            let layout = fallback_layout(&library, exit_block).unwrap();
+            assert_eq!(layout.len(), 2);
+            assert!(layout_is_solvable(&library, &layout));
+        }
+    }
+}
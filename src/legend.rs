@@ -0,0 +1,143 @@
+use std::{collections::HashMap, fmt};
+
+use bevy::{prelude::*, reflect::TypeUuid};
+
+use serde::Deserialize;
+
+use crate::colourscheme::ColourScheme;
+
+// what each character in a map block file turns into, loaded from
+// assets/map_blocks/blocks.legend.ron
+#[derive(Deserialize, TypeUuid, Clone)]
+#[uuid = "145eb6f8-6f4d-4b58-8019-ee7e48f9c62e"]
+#[serde(transparent)]
+pub struct Legend(pub HashMap<char, LegendEntry>);
+
+#[derive(Deserialize, Clone)]
+pub enum LegendEntry {
+    // nothing is spawned, the tile can be walked over
+    Floor,
+    // a floor that enemies in the same block walk between, in reading order
+    PatrolPoint,
+    Spawn(TileDef),
+    // the name of an enemy in assets/enemies/all.enemies.ron
+    Enemy(String),
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TileDef {
+    // index into the ascii sprite sheet
+    pub glyph: usize,
+    pub colour: ColourSlot,
+    #[serde(default)]
+    pub components: Vec<TileComponent>,
+    #[serde(default)]
+    pub spawn_chance: SpawnChance,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum ColourSlot {
+    Colour0,
+    Colour1,
+    Colour2,
+    // walls in the exit block are drawn in colour_0 so the exit stands out
+    Wall,
+}
+
+#[derive(Deserialize, Clone)]
+pub enum TileComponent {
+    Wall,
+    Exit,
+    Ammo,
+    // the name of a weapon in assets/weapons/all.weapons.ron
+    WeaponPickup(String),
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+pub enum SpawnChance {
+    #[default]
+    Always,
+    Fixed(f64),
+    // scale * stage_number * difficulty_coefficient, so it gets more likely every stage
+    Difficulty(f64),
+}
+
+impl SpawnChance {
+    pub fn probability(&self, stage_number: i32, difficulty_coefficient: f32) -> f64 {
+        let chance = match self {
+            SpawnChance::Always => 1.0,
+            SpawnChance::Fixed(chance) => *chance,
+            SpawnChance::Difficulty(scale) => {
+                scale * (stage_number as f32 * difficulty_coefficient) as f64
+            }
+        };
+        chance.clamp(0.0, 1.0)
+    }
+}
+
+impl ColourSlot {
+    pub fn resolve(&self, colours: &ColourScheme, exit_block: bool) -> Color {
+        match self {
+            ColourSlot::Colour0 => colours.colour_0,
+            ColourSlot::Colour1 => colours.colour_1,
+            ColourSlot::Colour2 => colours.colour_2,
+            ColourSlot::Wall if exit_block => colours.colour_0,
+            ColourSlot::Wall => colours.wall_colour,
+        }
+    }
+}
+
+impl Legend {
+    pub fn parse(path: &str, contents: &str) -> Result<Legend, MapLoadError> {
+        ron::from_str(contents).map_err(|error| MapLoadError {
+            file: path.to_string(),
+            line: error.position.line,
+            column: error.position.col,
+            message: error.code.to_string(),
+        })
+    }
+
+    pub fn get(&self, char: char) -> Option<&LegendEntry> {
+        self.0.get(&char)
+    }
+
+    fn has_component(&self, char: char, check: impl Fn(&TileComponent) -> bool) -> bool {
+        match self.get(char) {
+            Some(LegendEntry::Spawn(tile)) => tile.components.iter().any(check),
+            _ => false,
+        }
+    }
+
+    pub fn is_wall(&self, char: char) -> bool {
+        self.has_component(char, |component| matches!(component, TileComponent::Wall))
+    }
+
+    pub fn is_exit(&self, char: char) -> bool {
+        self.has_component(char, |component| matches!(component, TileComponent::Exit))
+    }
+}
+
+// something wrong in a map block or legend file, line and column start at 1 (0 if unknown)
+#[derive(Debug)]
+pub struct MapLoadError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // some errors, like a missing file, don't have a position
+        if self.line == 0 {
+            return write!(f, "{}: {}", self.file, self.message);
+        }
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for MapLoadError {}
@@ -1,6 +0,0 @@
-use bevy::prelude::*;
-
-#[derive(Component)]
-pub struct LivingEntity {
-    health: f32,
-}
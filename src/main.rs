@@ -1,11 +1,35 @@
+// systems take everything they use as arguments, so they're often long and their query types
+// complicated
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
 use bevy::{
-    prelude::*, render::camera::ScalingMode, render::texture::ImageSettings, window::WindowMode,
+    asset::AssetServerSettings, prelude::*, render::camera::ScalingMode,
+    render::texture::ImageSettings,
 };
 
-mod lib;
+mod actions;
+use actions::{Action, ActionsPlugin};
+
+mod ai;
+use ai::AiPlugin;
+
+mod boss;
+use boss::BossPlugin;
+
+mod camera;
+use camera::{CameraController, CameraPlugin};
+
+mod health;
+use health::HealthPlugin;
+
+mod bullet;
+use bullet::BulletPlugin;
+
+mod weapon;
+use weapon::{WeaponLibrary, WeaponPlugin};
 
 mod components;
-use components::{CameraFlag, MainMenuFlag, Manager};
+use components::{CameraFlag, MainMenuFlag};
 
 mod gameobject;
 use gameobject::GameObjectPlugin;
@@ -17,7 +41,7 @@ mod ui;
 use ui::{make_text_bundle, setup_ui, UiPlugin};
 
 mod tilemap;
-use tilemap::{generate_map, TileMapPlugin};
+use tilemap::{generate_map, StageContent, TileMapPlugin};
 
 mod player;
 use player::{respawn_player, PlayerPlugin};
@@ -25,9 +49,46 @@ use player::{respawn_player, PlayerPlugin};
 mod enemy;
 use enemy::EnemyPlugin;
 
+mod enemydef;
+use enemydef::{EnemyDefPlugin, EnemyLibrary};
+
+mod flocking;
+use flocking::FlockingPlugin;
+
+mod navigation;
+use navigation::NavigationPlugin;
+
+mod tilegrid;
+
+mod layout;
+mod legend;
+mod mapblock;
+use mapblock::{MapBlockLibrary, MapBlockPlugin};
+
+mod seed;
+use seed::{SeedPlugin, StageRng};
+
+mod run;
+use run::{RunPlugin, RunState};
+
 mod colourscheme;
 use colourscheme::{generate_colourscheme, ColourPlugin, ColourScheme};
 
+mod wfc;
+use wfc::{WfcModel, WfcPlugin};
+
+mod gameover;
+use gameover::GameOverPlugin;
+
+mod menu;
+use menu::MenuPlugin;
+
+mod pause;
+use pause::PausePlugin;
+
+mod settings;
+use settings::{Settings, SettingsPlugin};
+
 // mod colourscheme;
 // use colourscheme::ColourPlugin;
 
@@ -36,31 +97,67 @@ pub enum GameState {
     Playing,
     GameEnd,
     OpeningMenu,
+    // pushed on top of Playing, so everything in the stage stays where it is
+    Paused,
+    // pushed on top of Paused
+    Settings,
+}
+
+// sent to build a stage, the stage is made at the end of the frame so everything that
+// happened this frame has been dealt with first
+pub enum StageEvent {
+    // move on to the next stage
+    Next,
+    // make the current stage again, from the pause menu or when the map blocks are edited
+    Reload,
 }
 
 pub const TILE_SIZE: f32 = 25.0;
 
 fn main() {
+    // read before the app is built so the window opens with the saved mode and resolution
+    let settings = Settings::load();
     App::new()
         .insert_resource(ClearColor(Color::rgb_u8(0, 0, 0)))
         .insert_resource(ImageSettings::default_nearest()) // prevents blurry sprites
         .add_state(GameState::OpeningMenu)
-        .insert_resource(WindowDescriptor {
-            title: "Piko".to_string(),
-            mode: WindowMode::Fullscreen,
-            ..Default::default()
+        .insert_resource(settings.window_descriptor())
+        .insert_resource(settings)
+        // edited assets are reloaded while the game runs in debug builds
+        .insert_resource(AssetServerSettings {
+            watch_for_changes: cfg!(debug_assertions),
+            ..default()
         })
-        .add_startup_system_to_stage(StartupStage::PreStartup, game_manager_setup)
-        .add_startup_system(main_menu_setup)
+        .add_event::<StageEvent>()
+        .add_system_set(SystemSet::on_enter(GameState::OpeningMenu).with_system(main_menu_setup))
         .add_system_set(SystemSet::on_update(GameState::OpeningMenu).with_system(main_menu))
+        .add_system_to_stage(CoreStage::PostUpdate, make_new_stage)
         .add_plugins(DefaultPlugins)
+        .add_plugin(ActionsPlugin)
+        .add_plugin(CameraPlugin)
+        .add_plugin(HealthPlugin)
+        .add_plugin(BulletPlugin)
+        .add_plugin(WeaponPlugin)
+        .add_plugin(MapBlockPlugin)
+        .add_plugin(WfcPlugin)
+        .add_plugin(RunPlugin)
+        .add_plugin(SeedPlugin)
         .add_plugin(ColourPlugin)
         .add_plugin(EnemyPlugin)
+        .add_plugin(EnemyDefPlugin)
+        .add_plugin(AiPlugin)
+        .add_plugin(BossPlugin)
+        .add_plugin(FlockingPlugin)
+        .add_plugin(NavigationPlugin)
         .add_plugin(AsciiPlugin)
         .add_plugin(TileMapPlugin)
         .add_plugin(GameObjectPlugin)
         .add_plugin(UiPlugin)
         .add_plugin(PlayerPlugin)
+        .add_plugin(GameOverPlugin)
+        .add_plugin(MenuPlugin)
+        .add_plugin(PausePlugin)
+        .add_plugin(SettingsPlugin)
         .add_startup_system(spawn_camera)
         .run();
 }
@@ -108,7 +205,7 @@ fn main_menu_setup(
         .insert(MainMenuFlag)
         .insert(Name::new("Logo"));
 
-    let background_image = commands.spawn_bundle(SpriteBundle{
+    commands.spawn_bundle(SpriteBundle{
         sprite: Sprite {
             custom_size: Some(Vec2::splat(window.width() * 100.0)),
             ..default()
@@ -127,23 +224,23 @@ fn main_menu_setup(
 
 fn main_menu(
     mut commands: Commands,
-    keys: Res<Input<KeyCode>>,
-    mut state: ResMut<State<GameState>>,
+    actions: Res<Input<Action>>,
     mut menu_query: Query<Entity, With<MainMenuFlag>>,
-    mut ascii: Res<AsciiSheet>,
-    mut entities_query: Query<Entity, Without<Manager>>,
-    mut assets: Res<AssetServer>,
-    colours: Res<ColourScheme>,
-    mut manager_query: Query<&mut Manager, With<Manager>>,
+    library: Option<Res<MapBlockLibrary>>,
+    weapons: Option<Res<WeaponLibrary>>,
+    enemies: Option<Res<EnemyLibrary>>,
+    mut stage_events: EventWriter<StageEvent>,
 ) {
-    if keys.just_pressed(KeyCode::Return) {
+    // wait for the map blocks, weapons and enemies to finish loading
+    if library.is_none() || weapons.is_none() || enemies.is_none() {
+        return;
+    }
+    // make_new_stage switches to Playing once the stage is built
+    if actions.just_pressed(Action::Confirm) {
         for menu_element in menu_query.iter_mut() {
             commands.entity(menu_element).despawn();
         }
-        make_new_stage(commands, ascii, entities_query, assets, colours, &mut manager_query);
-        state
-            .set(GameState::Playing)
-            .expect("Failed to change game state.");
+        stage_events.send(StageEvent::Next);
     }
 }
 
@@ -152,41 +249,74 @@ fn spawn_camera(mut commands: Commands) {
 
     camera.projection.scaling_mode = ScalingMode::WindowSize;
 
-    commands.spawn_bundle(camera).insert(CameraFlag);
+    commands
+        .spawn_bundle(camera)
+        .insert(CameraFlag)
+        .insert(CameraController::default());
 }
 
 fn make_new_stage(
     mut commands: Commands,
+    mut stage_events: EventReader<StageEvent>,
     mut ascii: Res<AsciiSheet>,
-    mut entities_query: Query<Entity, Without<Manager>>,
-    mut assets: Res<AssetServer>,
-    colours: Res<ColourScheme>,
-    mut manager_query: &mut Query<&mut Manager, With<Manager>>,
-    // mut manager_query: Query<&mut Manager, With<Manager>>,
+    mut entities_query: Query<Entity>,
+    assets: Res<AssetServer>,
+    mut run: ResMut<RunState>,
+    mut state: ResMut<State<GameState>>,
+    library: Option<Res<MapBlockLibrary>>,
+    enemies: Option<Res<EnemyLibrary>>,
+    wfc: Option<Res<WfcModel>>,
+    mut rng: ResMut<StageRng>,
 ) {
-    generate_colourscheme(&mut commands);
-    let mut manager = manager_query.single_mut();
-    manager.stage_number += 1;
+    // only one stage gets made a frame, however many events were sent
+    let mut next_stage = false;
+    let mut reload = false;
+    for event in stage_events.iter() {
+        match event {
+            StageEvent::Next => next_stage = true,
+            StageEvent::Reload => reload = *state.current() == GameState::Playing,
+        }
+    }
+    let (library, enemies) = match (library, enemies) {
+        (Some(library), Some(enemies)) if next_stage || reload => (library, enemies),
+        _ => return,
+    };
+
+    // a new stage starts from what the player left the last one with, making the same
+    // stage again starts from what they had when they first got there
+    if next_stage {
+        run.next_stage();
+    } else {
+        run.restart_stage();
+    }
+    // everything random about the stage comes from here on, so a seed and stage number
+    // always give the same stage
+    *rng = StageRng::for_stage(run.seed, run.stage_number);
+    let colours = generate_colourscheme(&mut commands, &mut rng.0);
     for entity in entities_query.iter_mut() {
         commands.entity(entity).despawn();
     }
     setup_ui(&mut commands, &assets);
     generate_map(
         &mut commands,
-        &mut ascii,
-        &mut assets,
-        &colours,
-        &mut manager_query,
+        &ascii,
+        &StageContent {
+            library: &library,
+            enemies: &enemies,
+            wfc: wfc.as_deref(),
+            colours: &colours,
+            run: &run,
+        },
+        &mut rng.0,
     );
-    respawn_player(&mut commands, &mut ascii, &colours);
+    respawn_player(&mut commands, &mut ascii, &colours, &run);
     spawn_camera(commands);
-}
 
-fn game_manager_setup(mut commands: Commands) {
-    let game_manager = commands.spawn().id();
-    commands.entity(game_manager).insert(Manager {
-        difficulty_coefficient: 0.1,
-        player_ammo: 3,
-        stage_number: 1,
-    });
+    // starting from the title or game over screen, the state only changes once the stage is
+    // here. switching first would run the Playing systems for a frame with no player
+    if *state.current() != GameState::Playing {
+        state
+            .set(GameState::Playing)
+            .expect("Failed to change game state.");
+    }
 }
@@ -0,0 +1,613 @@
+use std::path::Path;
+
+use bevy::{
+    asset::{AssetLoader, HandleId, LoadContext, LoadState, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+
+use crate::{
+    layout::fallback_layout,
+    legend::{Legend, MapLoadError},
+    wfc::{WfcModel, WfcSettings},
+    StageEvent,
+};
+
+// width and height of a map block in tiles
+pub const MAP_BLOCK_TILES: i32 = 32;
+
+pub struct MapBlockPlugin;
+
+impl Plugin for MapBlockPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<MapBlockData>()
+            .add_asset::<Legend>()
+            .init_asset_loader::<MapBlockLoader>()
+            .init_asset_loader::<LegendLoader>()
+            .add_startup_system(load_map_blocks)
+            .add_system(build_map_block_library);
+    }
+}
+
+// which edges of a block can be walked through. neighbouring blocks have to agree on the
+// edge they share, and a closed edge is expected to be walled off in the block itself
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Openings {
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl Openings {
+    pub const ALL: Openings = Openings {
+        up: true,
+        down: true,
+        left: true,
+        right: true,
+    };
+
+    // "udlr" style flags, 'a' on its own means every edge is open
+    pub fn from_flags(flags: &str) -> Option<Openings> {
+        let mut openings = Openings::default();
+        for flag in flags.trim().chars() {
+            match flag {
+                'u' => openings.up = true,
+                'd' => openings.down = true,
+                'l' => openings.left = true,
+                'r' => openings.right = true,
+                'a' => openings = Openings::ALL,
+                _ => return None,
+            }
+        }
+        Some(openings)
+    }
+}
+
+#[derive(TypeUuid, Clone)]
+#[uuid = "3c025712-0fb7-47a1-8a5a-583bdd3fa582"]
+pub struct MapBlockData {
+    pub name: String,
+    // path inside assets/, used in error messages
+    pub path: String,
+    // rows[0] is the first line of the file, which is the top of the block in game
+    pub rows: Vec<Vec<char>>,
+    // line of the file rows[0] is on, the header lines come before it
+    first_line: usize,
+    // None until the block is checked against the legend, unless the file says what they are
+    openings: Option<Openings>,
+    // whether rotated and mirrored copies get made, turned off with !no_variants
+    variants: bool,
+}
+
+// the ways a block can be turned into a new one without changing what's in it
+#[derive(Clone, Copy)]
+enum Variant {
+    // clockwise
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    // left and right swap
+    MirrorX,
+    // top and bottom swap
+    MirrorY,
+}
+
+impl Variant {
+    const ALL: [Variant; 5] = [
+        Variant::Rotate90,
+        Variant::Rotate180,
+        Variant::Rotate270,
+        Variant::MirrorX,
+        Variant::MirrorY,
+    ];
+
+    fn suffix(&self) -> &'static str {
+        match self {
+            Variant::Rotate90 => "r90",
+            Variant::Rotate180 => "r180",
+            Variant::Rotate270 => "r270",
+            Variant::MirrorX => "mx",
+            Variant::MirrorY => "my",
+        }
+    }
+
+    // the tile in the original block that ends up at x, row
+    fn source(&self, x: i32, row: i32) -> (i32, i32) {
+        let last = MAP_BLOCK_TILES - 1;
+        match self {
+            Variant::Rotate90 => (row, last - x),
+            Variant::Rotate180 => (last - x, last - row),
+            Variant::Rotate270 => (last - row, x),
+            Variant::MirrorX => (last - x, row),
+            Variant::MirrorY => (x, last - row),
+        }
+    }
+
+    fn openings(&self, openings: Openings) -> Openings {
+        let Openings {
+            up,
+            down,
+            left,
+            right,
+        } = openings;
+        match self {
+            Variant::Rotate90 => Openings {
+                up: left,
+                down: right,
+                left: down,
+                right: up,
+            },
+            Variant::Rotate180 => Openings {
+                up: down,
+                down: up,
+                left: right,
+                right: left,
+            },
+            Variant::Rotate270 => Openings {
+                up: right,
+                down: left,
+                left: up,
+                right: down,
+            },
+            Variant::MirrorX => Openings {
+                left: right,
+                right: left,
+                ..openings
+            },
+            Variant::MirrorY => Openings {
+                up: down,
+                down: up,
+                ..openings
+            },
+        }
+    }
+}
+
+impl MapBlockData {
+    // tile at column x of row, anything past the end of a short line is floor
+    pub fn tile(&self, x: i32, row: i32) -> char {
+        if x < 0 || row < 0 {
+            return '.';
+        }
+        self.rows
+            .get(row as usize)
+            .and_then(|line| line.get(x as usize))
+            .copied()
+            .unwrap_or('.')
+    }
+
+    pub fn openings(&self) -> Openings {
+        self.openings.unwrap_or_default()
+    }
+
+    // a block that didn't come from a file, like the ones the wfc generator makes
+    pub fn from_rows(name: String, rows: Vec<Vec<char>>, openings: Openings) -> MapBlockData {
+        MapBlockData {
+            path: name.clone(),
+            name,
+            rows,
+            first_line: 0,
+            openings: Some(openings),
+            variants: false,
+        }
+    }
+
+    // a copy of the block turned or flipped, the openings are turned to match
+    fn variant(&self, variant: Variant) -> MapBlockData {
+        let rows = (0..MAP_BLOCK_TILES)
+            .map(|row| {
+                (0..MAP_BLOCK_TILES)
+                    .map(|x| {
+                        let (source_x, source_row) = variant.source(x, row);
+                        self.tile(source_x, source_row)
+                    })
+                    .collect()
+            })
+            .collect();
+        MapBlockData {
+            name: format!("{}:{}", self.name, variant.suffix()),
+            path: self.path.clone(),
+            rows,
+            first_line: self.first_line,
+            openings: Some(variant.openings(self.openings())),
+            variants: false,
+        }
+    }
+
+    // the block with every short line padded out with floor, so it compares equal to its
+    // variants when they come out the same
+    fn padded_rows(&self) -> Vec<Vec<char>> {
+        (0..MAP_BLOCK_TILES)
+            .map(|row| (0..MAP_BLOCK_TILES).map(|x| self.tile(x, row)).collect())
+            .collect()
+    }
+
+    // every tile character has to be in the legend, and blocks that didn't say which edges
+    // are open get them worked out from the walls
+    fn check_legend(&mut self, legend: &Legend) -> Result<(), MapLoadError> {
+        for (row, line) in self.rows.iter().enumerate() {
+            for (column, char) in line.iter().enumerate() {
+                if legend.get(*char).is_none() {
+                    return Err(MapLoadError {
+                        file: self.path.clone(),
+                        line: self.first_line + row + 1,
+                        column: column + 1,
+                        message: format!("'{}' isn't in the map block legend", char),
+                    });
+                }
+            }
+        }
+        if self.openings.is_none() {
+            self.openings = Some(edge_openings(self, legend));
+        }
+        Ok(())
+    }
+}
+
+// a block file is a grid of up to 32x32 tile characters, optionally preceded by header lines
+// starting with '!':
+//
+//   !open: udlr    edges that can be walked through (up, down, left, right)
+//   !no_variants   don't make rotated and mirrored copies of the block
+//
+// without an !open header the openings come from the file name (mb_udlr.txt), and failing
+// that from whichever edges aren't completely walled off
+pub fn parse_map_block(path: &str, contents: &str) -> Result<MapBlockData, MapLoadError> {
+    let name = block_name(Path::new(path));
+    let error = |line: usize, message: String| MapLoadError {
+        file: path.to_string(),
+        line: line + 1,
+        column: 1,
+        message,
+    };
+
+    let mut openings = None;
+    let mut variants = true;
+    let mut rows = Vec::new();
+    let mut first_line = 0;
+    for (line_number, line) in contents.lines().enumerate() {
+        if let Some(header) = line.strip_prefix('!') {
+            if !rows.is_empty() {
+                return Err(error(
+                    line_number,
+                    "headers have to come before the tiles".to_string(),
+                ));
+            }
+            if let Some(flags) = header.trim().strip_prefix("open:") {
+                openings = Some(Openings::from_flags(flags).ok_or_else(|| {
+                    error(
+                        line_number,
+                        format!("couldn't read openings \"{}\"", flags.trim()),
+                    )
+                })?);
+            } else if header.trim() == "no_variants" {
+                variants = false;
+            } else {
+                return Err(error(line_number, format!("unknown header \"{}\"", line)));
+            }
+            first_line = line_number + 1;
+            continue;
+        }
+        let row: Vec<char> = line.trim_end().chars().collect();
+        if row.len() > MAP_BLOCK_TILES as usize {
+            return Err(MapLoadError {
+                column: MAP_BLOCK_TILES as usize + 1,
+                ..error(
+                    line_number,
+                    format!("rows can't be wider than {} tiles", MAP_BLOCK_TILES),
+                )
+            });
+        }
+        if rows.len() == MAP_BLOCK_TILES as usize {
+            // blank lines at the end of the file are fine
+            if row.is_empty() {
+                continue;
+            }
+            return Err(error(
+                line_number,
+                format!("blocks can't be taller than {} rows", MAP_BLOCK_TILES),
+            ));
+        }
+        rows.push(row);
+    }
+
+    if openings.is_none() {
+        openings = name
+            .rsplit_once('_')
+            .and_then(|(_, flags)| Openings::from_flags(flags));
+    }
+    Ok(MapBlockData {
+        name,
+        path: path.to_string(),
+        rows,
+        first_line,
+        openings,
+        variants,
+    })
+}
+
+fn edge_openings(block: &MapBlockData, legend: &Legend) -> Openings {
+    let last = MAP_BLOCK_TILES - 1;
+    let edge = 0..MAP_BLOCK_TILES;
+    let open = |x: i32, row: i32| !legend.is_wall(block.tile(x, row));
+    Openings {
+        up: edge.clone().any(|x| open(x, 0)),
+        down: edge.clone().any(|x| open(x, last)),
+        left: edge.clone().any(|row| open(0, row)),
+        right: edge.clone().any(|row| open(last, row)),
+    }
+}
+
+#[derive(Default)]
+pub struct MapBlockLoader;
+
+impl AssetLoader for MapBlockLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let path = load_context.path().display().to_string();
+            let block = parse_map_block(&path, std::str::from_utf8(bytes)?)?;
+            load_context.set_default_asset(LoadedAsset::new(block));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["txt"]
+    }
+}
+
+#[derive(Default)]
+pub struct LegendLoader;
+
+impl AssetLoader for LegendLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let path = load_context.path().display().to_string();
+            let legend = Legend::parse(&path, std::str::from_utf8(bytes)?)?;
+            load_context.set_default_asset(LoadedAsset::new(legend));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["legend.ron"]
+    }
+}
+
+// keeps everything in assets/map_blocks loaded for the whole game
+struct MapBlockHandles {
+    blocks: Vec<Handle<MapBlockData>>,
+    legend: Handle<Legend>,
+}
+
+impl MapBlockHandles {
+    fn ids(&self) -> impl Iterator<Item = HandleId> + '_ {
+        self.blocks
+            .iter()
+            .map(|handle| handle.id)
+            .chain(std::iter::once(self.legend.id))
+    }
+}
+
+fn load_map_blocks(mut commands: Commands, assets: Res<AssetServer>) {
+    let blocks = assets
+        .load_folder("map_blocks")
+        .expect("No map_blocks folder found in assets.")
+        .into_iter()
+        .filter(|handle| {
+            assets
+                .get_handle_path(handle)
+                .is_some_and(|path| path.path().extension() == Some("txt".as_ref()))
+        })
+        .map(|handle| handle.typed())
+        .collect();
+    commands.insert_resource(MapBlockHandles {
+        blocks,
+        legend: assets.load("map_blocks/blocks.legend.ron"),
+    });
+}
+
+// (re)builds the MapBlockLibrary once every block has loaded, and again whenever one of them
+// is edited while the game is running (hot reloading is on in debug builds)
+fn build_map_block_library(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    handles: Res<MapBlockHandles>,
+    blocks: Res<Assets<MapBlockData>>,
+    legends: Res<Assets<Legend>>,
+    mut block_events: EventReader<AssetEvent<MapBlockData>>,
+    mut legend_events: EventReader<AssetEvent<Legend>>,
+    library: Option<Res<MapBlockLibrary>>,
+    wfc: Res<WfcSettings>,
+    mut stage_events: EventWriter<StageEvent>,
+) {
+    let modified = block_events.iter().count() + legend_events.iter().count() > 0;
+    let load_state = assets.get_group_load_state(handles.ids());
+    if library.is_some() && (!modified || load_state != LoadState::Loaded) {
+        return;
+    }
+    match load_state {
+        LoadState::Loaded => {}
+        // the reason has already been logged by the asset server
+        LoadState::Failed => panic!("Couldn't load the map blocks."),
+        _ => return,
+    }
+
+    let block_data = handles
+        .blocks
+        .iter()
+        .filter_map(|handle| blocks.get(handle))
+        .cloned()
+        .collect();
+    let legend = legends.get(&handles.legend).unwrap().clone();
+    match MapBlockLibrary::new(block_data, legend) {
+        Ok(new_library) => {
+            // the generator learns from the same blocks, so it has to be rebuilt with them
+            if wfc.enabled {
+                let samples = new_library
+                    .fillers()
+                    .map(|block| &new_library.blocks[block]);
+                commands.insert_resource(WfcModel::new(samples));
+            }
+            commands.insert_resource(new_library);
+            if library.is_some() {
+                println!("map blocks changed, regenerating the stage");
+                stage_events.send(StageEvent::Reload);
+            }
+        }
+        // a mistake while editing shouldn't close the game, keep using the old blocks
+        Err(error) if library.is_some() => println!("{}", error),
+        Err(error) => panic!("{}", error),
+    }
+}
+
+// every block the generator can pick from, plus the three special blocks
+pub struct MapBlockLibrary {
+    pub blocks: Vec<MapBlockData>,
+    pub entrance: usize,
+    pub exit: usize,
+    // takes the place of the exit block on boss stages
+    pub arena: usize,
+    pub legend: Legend,
+}
+
+impl MapBlockLibrary {
+    pub fn new(
+        mut blocks: Vec<MapBlockData>,
+        legend: Legend,
+    ) -> Result<MapBlockLibrary, MapLoadError> {
+        for block in blocks.iter_mut() {
+            block.check_legend(&legend)?;
+        }
+        // the asset server loads in whatever order it likes, sort so a seed always gives the
+        // same map
+        blocks.sort_by(|a, b| a.name.cmp(&b.name));
+
+        // symmetric blocks give the same variant more than once, only keep the first
+        let mut variants: Vec<MapBlockData> = Vec::new();
+        for block in blocks.iter().filter(|block| block.variants) {
+            let mut seen = vec![block.padded_rows()];
+            for variant in Variant::ALL {
+                let variant = block.variant(variant);
+                if !seen.contains(&variant.rows) {
+                    seen.push(variant.rows.clone());
+                    variants.push(variant);
+                }
+            }
+        }
+        blocks.extend(variants);
+
+        let find = |name: &str| {
+            blocks
+                .iter()
+                .position(|block| block.name == name)
+                .ok_or_else(|| MapLoadError {
+                    file: "map_blocks".to_string(),
+                    line: 0,
+                    column: 0,
+                    message: format!("no {} map block found", name),
+                })
+        };
+        let entrance = find("entrance")?;
+        let exit = find("exit")?;
+        let arena = find("arena")?;
+        let library = MapBlockLibrary {
+            blocks,
+            entrance,
+            exit,
+            arena,
+            legend,
+        };
+
+        // every stage has to be able to fall back on the exit right next to the entrance
+        for (name, exit_block) in [("exit", exit), ("arena", arena)] {
+            if fallback_layout(&library, exit_block).is_none() {
+                return Err(MapLoadError {
+                    file: "map_blocks".to_string(),
+                    line: 0,
+                    column: 0,
+                    message: format!(
+                        "the {} map block can't be walked to from the entrance next to it",
+                        name
+                    ),
+                });
+            }
+        }
+        Ok(library)
+    }
+
+    // blocks that can be used anywhere in the map, i.e. not the entrance, exit or arena
+    pub fn fillers(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.blocks.len()).filter(move |index| {
+            *index != self.entrance && *index != self.exit && *index != self.arena
+        })
+    }
+}
+
+fn block_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+// every block in assets/map_blocks, read straight off the disk since tests have no asset server
+#[cfg(test)]
+pub fn test_library() -> MapBlockLibrary {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/map_blocks");
+    let read = |path: &Path| std::fs::read_to_string(path).expect("Couldn't read map block file.");
+    let legend_path = dir.join("blocks.legend.ron");
+    let legend = Legend::parse(&legend_path.display().to_string(), &read(&legend_path)).unwrap();
+    let mut blocks = Vec::new();
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|extension| extension == "txt") {
+            blocks.push(parse_map_block(&path.display().to_string(), &read(&path)).unwrap());
+        }
+    }
+    MapBlockLibrary::new(blocks, legend).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(width: usize, height: usize) -> String {
+        vec![".".repeat(width); height].join("\n")
+    }
+
+    #[test]
+    fn short_blocks_are_fine() {
+        assert!(parse_map_block("mb_a.txt", &block(31, 30)).is_ok());
+        assert!(parse_map_block("mb_a.txt", &format!("{}\n\n", block(32, 32))).is_ok());
+    }
+
+    #[test]
+    fn wide_rows_are_an_error() {
+        let mut contents = block(32, 32);
+        contents.insert_str(0, "!open: a\n.");
+        let error = match parse_map_block("mb_a.txt", &contents) {
+            Ok(_) => panic!("should have been too big"),
+            Err(error) => error,
+        };
+        assert_eq!((error.line, error.column), (2, 33));
+    }
+
+    #[test]
+    fn tall_blocks_are_an_error() {
+        let error = match parse_map_block("mb_a.txt", &block(32, 33)) {
+            Ok(_) => panic!("should have been too big"),
+            Err(error) => error,
+        };
+        assert_eq!((error.line, error.column), (33, 1));
+    }
+}
@@ -0,0 +1,103 @@
+use bevy::prelude::*;
+
+use crate::{actions::Action, ui::make_text_bundle};
+
+const SELECTED_COLOUR: Color = Color::WHITE;
+const UNSELECTED_COLOUR: Color = Color::GRAY;
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MenuCursor>()
+            .add_system(highlight_menu_items);
+    }
+}
+
+// one line of a list menu, the pause and settings menus are built out of these
+#[derive(Component)]
+pub struct MenuItem {
+    pub index: usize,
+}
+
+// which item of the menu on screen is selected, only one menu is shown at a time
+#[derive(Default)]
+pub struct MenuCursor(pub usize);
+
+// spawns a title and a column of options under it, returns every entity so the caller can
+// tag them for despawning later
+pub fn spawn_menu(
+    commands: &mut Commands,
+    assets: &Res<AssetServer>,
+    title: &str,
+    options: &[String],
+) -> Vec<Entity> {
+    let mut entities = vec![make_text_bundle(
+        commands,
+        assets,
+        50.0,
+        title.to_string(),
+        SELECTED_COLOUR,
+        menu_line_style(0, 0.0),
+    )];
+    // long menus are packed closer together so they still fit on the screen
+    let spacing = (75.0 / (options.len() + 2) as f32).min(7.0);
+    for (index, option) in options.iter().enumerate() {
+        let item = make_text_bundle(
+            commands,
+            assets,
+            30.0,
+            option.clone(),
+            UNSELECTED_COLOUR,
+            menu_line_style(index + 2, spacing),
+        );
+        commands.entity(item).insert(MenuItem { index });
+        entities.push(item);
+    }
+    entities
+}
+
+fn menu_line_style(line: usize, spacing: f32) -> Style {
+    Style {
+        align_self: AlignSelf::FlexEnd,
+        position_type: PositionType::Absolute,
+        position: UiRect {
+            top: Val::Percent(20.0 + line as f32 * spacing),
+            left: Val::Percent(35.0),
+            ..default()
+        },
+        ..default()
+    }
+}
+
+// moves the cursor with Up and Down and returns the picked item on Confirm. the press is
+// used up so a menu opened by it doesn't see it the same frame
+pub fn menu_input(
+    actions: &mut Input<Action>,
+    cursor: &mut MenuCursor,
+    option_count: usize,
+) -> Option<usize> {
+    if actions.clear_just_pressed(Action::Up) {
+        cursor.0 = (cursor.0 + option_count - 1) % option_count;
+    }
+    if actions.clear_just_pressed(Action::Down) {
+        cursor.0 = (cursor.0 + 1) % option_count;
+    }
+    if actions.clear_just_pressed(Action::Confirm) {
+        return Some(cursor.0);
+    }
+    None
+}
+
+fn highlight_menu_items(cursor: Res<MenuCursor>, mut item_query: Query<(&MenuItem, &mut Text)>) {
+    for (item, mut text) in item_query.iter_mut() {
+        let colour = if item.index == cursor.0 {
+            SELECTED_COLOUR
+        } else {
+            UNSELECTED_COLOUR
+        };
+        for section in text.sections.iter_mut() {
+            section.style.color = colour;
+        }
+    }
+}
@@ -0,0 +1,505 @@
+use std::{cmp::Ordering, collections::BinaryHeap, f32::consts::SQRT_2};
+
+use bevy::prelude::*;
+
+use crate::{
+    components::{Enemy, NavPath, Node, NodeGraph},
+    tilegrid::{tile_coords, tile_position, tile_translation, TileGrid, TILE_STEP},
+    GameState,
+};
+
+// extra cost for walking right next to a wall, keeps paths away from corners
+const NEAR_WALL_PENALTY: f32 = 0.5;
+// how many tiles the target can move away from the end of a path before it's re-planned
+const REPLAN_DISTANCE: i32 = 3;
+
+const NEIGHBOURS: [IVec2; 8] = [
+    IVec2::new(1, 0),
+    IVec2::new(-1, 0),
+    IVec2::new(0, 1),
+    IVec2::new(0, -1),
+    IVec2::new(1, 1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, 1),
+    IVec2::new(-1, -1),
+];
+
+pub struct NavigationPlugin;
+
+impl Plugin for NavigationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NodeGraph>()
+            .add_system(build_node_graph)
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(plan_enemy_paths.after(build_node_graph)),
+            );
+    }
+}
+
+// rebuild the graph whenever generate_map has filled in a new tile grid
+fn build_node_graph(mut graph: ResMut<NodeGraph>, grid: Res<TileGrid>) {
+    if grid.is_changed() {
+        *graph = NodeGraph::from_grid(&grid);
+    }
+}
+
+// a target that moves every frame would mean a search every frame, so paths are only
+// re-planned once the target is a few tiles from where the path ends, or the path has been
+// walked and the target isn't at the end of it
+fn plan_enemy_paths(
+    mut graph: ResMut<NodeGraph>,
+    mut enemy_query: Query<(&Enemy, &Transform, &mut NavPath), With<Enemy>>,
+) {
+    for (enemy, transform, mut path) in enemy_query.iter_mut() {
+        let goal = match enemy.move_target {
+            Some(target) => tile_coords(target),
+            None => continue,
+        };
+        let stale = match path.goal {
+            Some(old_goal) => {
+                (goal - old_goal).abs().max_element() > REPLAN_DISTANCE
+                    || (path.waypoints.is_empty() && goal != old_goal)
+            }
+            None => true,
+        };
+        if !stale {
+            continue;
+        }
+
+        path.goal = Some(goal);
+        path.waypoints = match graph.find_path(tile_coords(transform.translation), goal) {
+            Some(tiles) => tiles.into_iter().rev().map(tile_translation).collect(),
+            None => Vec::new(),
+        };
+    }
+}
+
+impl NavPath {
+    // drops any waypoints that have been reached and returns the one to steer towards
+    pub fn next_waypoint(&mut self, position: Vec3) -> Option<Vec3> {
+        while let Some(waypoint) = self.waypoints.last() {
+            if waypoint.truncate().distance(position.truncate()) < TILE_STEP * 0.5 {
+                self.waypoints.pop();
+            } else {
+                return Some(*waypoint);
+            }
+        }
+        None
+    }
+}
+
+impl NodeGraph {
+    // builds a graph over the tile grid with a tile of padding around it
+    pub fn from_grid(grid: &TileGrid) -> NodeGraph {
+        let (min, max) = grid.bounds();
+        let origin = min - IVec2::ONE;
+        let size = max - origin + IVec2::splat(2);
+        let is_blocked = |x: i32, y: i32| grid.is_solid(origin + IVec2::new(x, y));
+
+        let mut nodes = Vec::with_capacity(size.x as usize);
+        for x in 0..size.x {
+            let mut column = Vec::with_capacity(size.y as usize);
+            for y in 0..size.y {
+                let near_wall = NEIGHBOURS
+                    .iter()
+                    .any(|offset| is_blocked(x + offset.x, y + offset.y));
+
+                column.push(Node {
+                    x: x + origin.x,
+                    y: y + origin.y,
+                    f: f32::INFINITY,
+                    g: f32::INFINITY,
+                    h: 0.0,
+                    previous: None,
+                    walkable: !is_blocked(x, y),
+                    solid: is_blocked(x, y),
+                    penalty: if near_wall { NEAR_WALL_PENALTY } else { 0.0 },
+                    closed: false,
+                });
+            }
+            nodes.push(column);
+        }
+
+        NodeGraph { origin, nodes }
+    }
+
+    pub fn node(&self, coords: IVec2) -> Option<&Node> {
+        let local = coords - self.origin;
+        if local.x < 0 || local.y < 0 {
+            return None;
+        }
+        self.nodes
+            .get(local.x as usize)
+            .and_then(|column| column.get(local.y as usize))
+    }
+
+    fn node_mut(&mut self, coords: IVec2) -> Option<&mut Node> {
+        let local = coords - self.origin;
+        if local.x < 0 || local.y < 0 {
+            return None;
+        }
+        self.nodes
+            .get_mut(local.x as usize)
+            .and_then(|column| column.get_mut(local.y as usize))
+    }
+
+    pub fn is_walkable(&self, coords: IVec2) -> bool {
+        self.node(coords).is_some_and(|node| node.walkable)
+    }
+
+    pub fn is_solid(&self, coords: IVec2) -> bool {
+        self.node(coords).is_some_and(|node| node.solid)
+    }
+
+    // walks the tiles the segment passes through (Amanatides & Woo) and stops at the first wall
+    pub fn line_of_sight(&self, from: Vec3, to: Vec3) -> bool {
+        // shift by half a tile so each tile spans [n, n + 1) and floor() picks it
+        let start = tile_position(from) + Vec2::splat(0.5);
+        let end = tile_position(to) + Vec2::splat(0.5);
+        let direction = end - start;
+
+        let mut cell = start.floor().as_ivec2();
+        let end_cell = end.floor().as_ivec2();
+        let step = IVec2::new(sign(direction.x), sign(direction.y));
+        let t_delta = Vec2::new(1.0 / direction.x.abs(), 1.0 / direction.y.abs());
+        let mut t_max = Vec2::new(
+            first_crossing(start.x, direction.x),
+            first_crossing(start.y, direction.y),
+        );
+
+        loop {
+            if self.is_solid(cell) {
+                return false;
+            }
+            if cell == end_cell || t_max.min_element() > 1.0 {
+                return true;
+            }
+            if t_max.x < t_max.y {
+                t_max.x += t_delta.x;
+                cell.x += step.x;
+            } else {
+                t_max.y += t_delta.y;
+                cell.y += step.y;
+            }
+        }
+    }
+
+    // A* over the tile grid, returns the tiles to walk through after start, ending on goal
+    pub fn find_path(&mut self, start: IVec2, goal: IVec2) -> Option<Vec<IVec2>> {
+        if self.node(start).is_none() || !self.is_walkable(goal) {
+            return None;
+        }
+
+        // every node the search writes to, so only those need putting back afterwards
+        let mut touched = vec![start];
+        let mut open = BinaryHeap::new();
+        {
+            let start_node = self.node_mut(start).unwrap();
+            start_node.g = 0.0;
+            start_node.h = octile_distance(start, goal);
+            start_node.f = start_node.h;
+            open.push(OpenNode {
+                f: start_node.f,
+                coords: start,
+            });
+        }
+
+        while let Some(OpenNode {
+            coords: current, ..
+        }) = open.pop()
+        {
+            let current_g = {
+                let node = self.node_mut(current).unwrap();
+                if node.closed {
+                    continue;
+                }
+                node.closed = true;
+                node.g
+            };
+
+            if current == goal {
+                let path = self.reconstruct_path(start, goal);
+                self.reset(&touched);
+                return Some(path);
+            }
+
+            for offset in NEIGHBOURS.iter() {
+                let next = current + *offset;
+                let diagonal = offset.x != 0 && offset.y != 0;
+                // don't cut corners, both of the orthogonal tiles have to be free too
+                if diagonal
+                    && (!self.is_walkable(current + IVec2::new(offset.x, 0))
+                        || !self.is_walkable(current + IVec2::new(0, offset.y)))
+                {
+                    continue;
+                }
+
+                let node = match self.node_mut(next) {
+                    Some(node) if node.walkable && !node.closed => node,
+                    _ => continue,
+                };
+                let step = if diagonal { SQRT_2 } else { 1.0 };
+                let g = current_g + step + node.penalty;
+                if g < node.g {
+                    if node.g == f32::INFINITY {
+                        touched.push(next);
+                    }
+                    node.g = g;
+                    node.h = octile_distance(next, goal);
+                    node.f = node.g + node.h;
+                    node.previous = Some(current);
+                    open.push(OpenNode {
+                        f: node.f,
+                        coords: next,
+                    });
+                }
+            }
+        }
+
+        self.reset(&touched);
+        None
+    }
+
+    fn reset(&mut self, touched: &[IVec2]) {
+        for coords in touched.iter() {
+            if let Some(node) = self.node_mut(*coords) {
+                node.f = f32::INFINITY;
+                node.g = f32::INFINITY;
+                node.h = 0.0;
+                node.previous = None;
+                node.closed = false;
+            }
+        }
+    }
+
+    fn reconstruct_path(&self, start: IVec2, goal: IVec2) -> Vec<IVec2> {
+        let mut path = Vec::new();
+        let mut node = self.node(goal);
+        while let Some(current) = node {
+            let coords = IVec2::new(current.x, current.y);
+            if coords == start {
+                break;
+            }
+            path.push(coords);
+            node = current.previous.and_then(|previous| self.node(previous));
+        }
+        path.reverse();
+        path
+    }
+}
+
+fn sign(value: f32) -> i32 {
+    if value > 0.0 {
+        1
+    } else if value < 0.0 {
+        -1
+    } else {
+        0
+    }
+}
+
+// fraction of the segment travelled before it first crosses a tile edge on this axis
+fn first_crossing(start: f32, direction: f32) -> f32 {
+    if direction > 0.0 {
+        (start.floor() + 1.0 - start) / direction
+    } else if direction < 0.0 {
+        (start - start.floor()) / -direction
+    } else {
+        f32::INFINITY
+    }
+}
+
+fn octile_distance(a: IVec2, b: IVec2) -> f32 {
+    let d = (a - b).abs();
+    let (low, high) = (d.x.min(d.y) as f32, d.x.max(d.y) as f32);
+    high + (SQRT_2 - 1.0) * low
+}
+
+// BinaryHeap is a max heap, so compare the other way round to pop the lowest f first
+struct OpenNode {
+    f: f32,
+    coords: IVec2,
+}
+
+impl PartialEq for OpenNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.f == other.f
+    }
+}
+
+impl Eq for OpenNode {}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.f.partial_cmp(&self.f).unwrap_or(Ordering::Equal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // '#' is a wall and anything else is floor, the first row is the top of the grid
+    fn graph(rows: &[&str]) -> NodeGraph {
+        let height = rows.len() as i32;
+        let walls: Vec<IVec2> = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(row, line)| {
+                line.chars()
+                    .enumerate()
+                    .filter(|(_, char)| *char == '#')
+                    .map(move |(x, _)| IVec2::new(x as i32, height - 1 - row as i32))
+            })
+            .collect();
+        NodeGraph::from_grid(&TileGrid::new(&walls, Vec::new()))
+    }
+
+    fn assert_connected(graph: &NodeGraph, start: IVec2, path: &[IVec2]) {
+        let mut previous = start;
+        for tile in path.iter() {
+            let step = (*tile - previous).abs();
+            assert!(
+                step.max_element() == 1,
+                "{:?} to {:?} isn't one step",
+                previous,
+                tile
+            );
+            assert!(graph.is_walkable(*tile), "{:?} isn't walkable", tile);
+            previous = *tile;
+        }
+    }
+
+    #[test]
+    fn straight_path() {
+        let mut graph = graph(&[
+            "###########",
+            "#.........#",
+            "#.........#",
+            "#.........#",
+            "#.........#",
+            "#.........#",
+            "###########",
+        ]);
+        let path = graph.find_path(IVec2::new(2, 3), IVec2::new(8, 3)).unwrap();
+        let expected: Vec<IVec2> = (3..=8).map(|x| IVec2::new(x, 3)).collect();
+        assert_eq!(path, expected);
+    }
+
+    #[test]
+    fn path_around_wall() {
+        let mut graph = graph(&[
+            "###########",
+            "#.........#",
+            "#.........#",
+            "#....#....#",
+            "#....#....#",
+            "#....#....#",
+            "#....#....#",
+            "###########",
+        ]);
+        let start = IVec2::new(2, 2);
+        let goal = IVec2::new(8, 2);
+        let path = graph.find_path(start, goal).unwrap();
+        assert_eq!(path.last(), Some(&goal));
+        assert_connected(&graph, start, &path);
+        // it has to go up and over the top of the wall
+        assert!(path.iter().all(|tile| tile.x != 5 || tile.y >= 5));
+        assert!(path.len() > 6);
+    }
+
+    #[test]
+    fn unreachable_goal() {
+        let mut graph = graph(&[
+            "###########",
+            "#....#....#",
+            "#....#....#",
+            "#....#....#",
+            "###########",
+        ]);
+        assert_eq!(graph.find_path(IVec2::new(2, 2), IVec2::new(8, 2)), None);
+        // a goal in a wall can't be reached either
+        assert_eq!(graph.find_path(IVec2::new(2, 2), IVec2::new(5, 2)), None);
+    }
+
+    #[test]
+    fn start_is_goal() {
+        let mut graph = graph(&["#####", "#...#", "#...#", "#...#", "#####"]);
+        let tile = IVec2::new(2, 2);
+        assert_eq!(graph.find_path(tile, tile), Some(Vec::new()));
+    }
+
+    #[test]
+    fn one_wide_corridor() {
+        let mut graph = graph(&[
+            "###########",
+            "#...###...#",
+            "#.........#",
+            "#...###...#",
+            "###########",
+        ]);
+        let start = IVec2::new(1, 2);
+        let goal = IVec2::new(9, 2);
+        let path = graph.find_path(start, goal).unwrap();
+        assert_connected(&graph, start, &path);
+        assert!((4..=6).all(|x| path.contains(&IVec2::new(x, 2))));
+    }
+
+    #[test]
+    fn searches_dont_leak() {
+        let mut graph = pillar_room();
+        let start = IVec2::new(1, 2);
+        let goal = IVec2::new(9, 2);
+        let first = graph.find_path(start, goal);
+        // a failed search in between mustn't leave anything behind either
+        assert_eq!(graph.find_path(start, IVec2::new(4, 2)), None);
+        graph.find_path(goal, start).unwrap();
+        assert_eq!(graph.find_path(start, goal), first);
+        for node in graph.nodes.iter().flatten() {
+            assert!(node.g == f32::INFINITY && node.previous.is_none() && !node.closed);
+        }
+    }
+
+    // a pillar at (4, 2) in the middle of a room
+    fn pillar_room() -> NodeGraph {
+        graph(&[
+            "###########",
+            "#.........#",
+            "#...#.....#",
+            "#.........#",
+            "###########",
+        ])
+    }
+
+    fn sight(graph: &NodeGraph, from: (i32, i32), to: (i32, i32)) -> bool {
+        let from = tile_translation(IVec2::new(from.0, from.1));
+        let to = tile_translation(IVec2::new(to.0, to.1));
+        graph.line_of_sight(from, to) && graph.line_of_sight(to, from)
+    }
+
+    #[test]
+    fn line_of_sight_clear() {
+        let graph = pillar_room();
+        assert!(sight(&graph, (1, 3), (9, 3)));
+        assert!(sight(&graph, (1, 1), (9, 1)));
+        assert!(sight(&graph, (1, 1), (3, 3)));
+        assert!(sight(&graph, (5, 1), (5, 3)));
+    }
+
+    #[test]
+    fn line_of_sight_blocked() {
+        let graph = pillar_room();
+        assert!(!sight(&graph, (1, 2), (9, 2)));
+        assert!(!sight(&graph, (2, 1), (6, 3)));
+        assert!(!sight(&graph, (4, 1), (4, 3)));
+        // the outer wall is solid too
+        assert!(!sight(&graph, (1, 1), (1, 5)));
+    }
+}
@@ -0,0 +1,92 @@
+use bevy::prelude::*;
+
+use crate::{
+    actions::Action,
+    components::PauseMenuFlag,
+    gameover::{clear_for_title, reset_run},
+    menu::{menu_input, spawn_menu, MenuCursor},
+    run::RunState,
+    GameState, StageEvent,
+};
+
+const PAUSE_OPTIONS: [&str; 4] = ["Resume", "Restart stage", "Settings", "Quit to title"];
+
+pub struct PausePlugin;
+
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_update(GameState::Playing).with_system(pause_game))
+            // the menu is hidden while the settings screen is open on top of it
+            .add_system_set(SystemSet::on_enter(GameState::Paused).with_system(pause_menu_setup))
+            .add_system_set(SystemSet::on_resume(GameState::Paused).with_system(pause_menu_setup))
+            .add_system_set(SystemSet::on_update(GameState::Paused).with_system(pause_menu))
+            .add_system_set(SystemSet::on_pause(GameState::Paused).with_system(despawn_pause_menu))
+            .add_system_set(SystemSet::on_exit(GameState::Paused).with_system(despawn_pause_menu));
+    }
+}
+
+// everything that only runs while Playing stops until the Paused state is popped again
+fn pause_game(mut actions: ResMut<Input<Action>>, mut state: ResMut<State<GameState>>) {
+    if actions.clear_just_pressed(Action::Pause) {
+        // Escape is both Pause and Cancel, the menu mustn't close on the same press
+        actions.clear_just_pressed(Action::Cancel);
+        state
+            .push(GameState::Paused)
+            .expect("Failed to change game state.");
+    }
+}
+
+fn pause_menu_setup(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut cursor: ResMut<MenuCursor>,
+) {
+    cursor.0 = 0;
+    let options: Vec<String> = PAUSE_OPTIONS
+        .iter()
+        .map(|option| option.to_string())
+        .collect();
+    for entity in spawn_menu(&mut commands, &assets, "Paused", &options) {
+        commands.entity(entity).insert(PauseMenuFlag);
+    }
+}
+
+fn despawn_pause_menu(mut commands: Commands, menu_query: Query<Entity, With<PauseMenuFlag>>) {
+    for entity in menu_query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+fn pause_menu(
+    commands: Commands,
+    mut actions: ResMut<Input<Action>>,
+    mut cursor: ResMut<MenuCursor>,
+    mut state: ResMut<State<GameState>>,
+    entities_query: Query<Entity>,
+    mut run: ResMut<RunState>,
+    mut stage_events: EventWriter<StageEvent>,
+) {
+    if actions.clear_just_pressed(Action::Pause) || actions.clear_just_pressed(Action::Cancel) {
+        state.pop().expect("Failed to change game state.");
+        return;
+    }
+
+    match menu_input(&mut actions, &mut cursor, PAUSE_OPTIONS.len()) {
+        Some(0) => state.pop().expect("Failed to change game state."),
+        Some(1) => {
+            state.pop().expect("Failed to change game state.");
+            stage_events.send(StageEvent::Reload);
+        }
+        Some(2) => state
+            .push(GameState::Settings)
+            .expect("Failed to change game state."),
+        Some(3) => {
+            reset_run(&mut run);
+            clear_for_title(commands, &entities_query);
+            state
+                .replace(GameState::OpeningMenu)
+                .expect("Failed to change game state.");
+        }
+        _ => {}
+    }
+}
@@ -1,24 +1,28 @@
-use bevy::{
-    prelude::*,
-    sprite::collide_aabb::{collide, Collision},
-};
+use bevy::prelude::*;
 
 use crate::{
+    actions::{Action, ActionAxes, Aim},
+    ai::NoiseEvent,
     ascii::{spawn_ascii_sprite, AsciiSheet},
+    bullet::BulletPool,
+    camera::ScreenShake,
     colourscheme::ColourScheme,
-    components::{
-        Ammo, Bullet, CameraFlag, Enemy, EnemyFlock, Exit, Manager, Player, TileCollider,
-    },
-    enemy::set_magnitude,
-    make_new_stage, GameState, TILE_SIZE,
+    components::{Ammo, Exit, LivingEntity, LockedExit, Player},
+    health::DeathEvent,
+    tilegrid::TileGrid,
+    run::RunState,
+    seed::StageRng,
+    weapon::{fire_volley, Weapon, WeaponLibrary},
+    GameState, StageEvent, TILE_SIZE,
 };
 
-use crate::tilemap::{MAP_BLOCK_X, MAP_BLOCK_Y};
-
 const PLAYER_SPEED: f32 = 520.0;
 const PLAYER_MAX_SPEED: f32 = 400.0;
-const STARTING_PLAYER_AMMO: i32 = 3;
-const PLAYER_HEALTH: i32 = 4;
+pub const PLAYER_HEALTH: f32 = 4.0;
+// seconds the player can't be hurt again for after a hit
+const PLAYER_INVULNERABLE_TIME: f32 = 1.0;
+// enemies this close hear the player fire, even ones that can't see them
+const SHOT_NOISE_RADIUS: f32 = TILE_SIZE * 12.0;
 
 pub struct PlayerPlugin;
 
@@ -28,10 +32,8 @@ impl Plugin for PlayerPlugin {
             SystemSet::on_update(GameState::Playing)
                 .with_system(player_health)
                 .with_system(player_shoot)
-                .with_system(camera_follow)
                 .with_system(player_exit)
                 .with_system(player_controller)
-                .with_system(update_bullets)
                 .with_system(player_phys_update)
                 .with_system(player_ammo_check),
         )
@@ -39,92 +41,49 @@ impl Plugin for PlayerPlugin {
     }
 }
 
-pub fn spawn_player(mut commands: Commands, ascii: Res<AsciiSheet>, colours: Res<ColourScheme>) {
-    let player = spawn_ascii_sprite(
-        &mut commands,
-        &ascii,
-        3,
-        colours.colour_1,
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec2::splat(TILE_SIZE * 0.98),
-    );
-    commands
-        .entity(player)
-        .insert(Name::new("Player"))
-        .insert(Player {
-            speed: PLAYER_SPEED,
-            health: PLAYER_HEALTH,
-            shoot_timer: Timer::from_seconds(1.0, false),
-            ammo: STARTING_PLAYER_AMMO,
-            velocity: Vec3::splat(0.0),
-            acceleration: Vec3::splat(0.0),
-            max_speed: PLAYER_MAX_SPEED,
-        });
-}
-
-pub struct MoveDirections {
-    up: Vec3,
-    down: Vec3,
-    left: Vec3,
-    right: Vec3,
+pub fn spawn_player(
+    mut commands: Commands,
+    mut ascii: Res<AsciiSheet>,
+    colours: Res<ColourScheme>,
+    run: Res<RunState>,
+) {
+    respawn_player(&mut commands, &mut ascii, &colours, &run);
 }
 
 fn player_controller(
-    mut query: Query<(&mut Player, &mut Transform), With<Player>>,
-    mut tile_query: Query<
-        (&Transform, &TileCollider),
-        (With<TileCollider>, Without<Player>, Without<EnemyFlock>),
-    >,
-    keys: Res<Input<KeyCode>>,
+    mut query: Query<&mut Player>,
+    action_axes: Res<ActionAxes>,
     time: Res<Time>,
 ) {
-    let (mut player, transform) = query.single_mut();
-
-    let move_directions = MoveDirections {
-        up: Vec3::new(0.0, player.speed * time.delta_seconds(), 0.0),
-        down: Vec3::new(0.0, player.speed * time.delta_seconds() * -1.0, 0.0),
-        left: Vec3::new((player.speed * time.delta_seconds()) * -1.0, 0.0, 0.0),
-        right: Vec3::new(player.speed * time.delta_seconds(), 0.0, 0.0),
+    let mut player = match query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
     };
 
-    let mut move_vector = Vec3::splat(0.0);
-
-    if keys.pressed(KeyCode::W) {
-        move_vector += move_directions.up;
-    }
-    if keys.pressed(KeyCode::S) {
-        move_vector += move_directions.down;
-    }
-
-    if keys.pressed(KeyCode::A) {
-        move_vector += move_directions.left;
-    }
-    if keys.pressed(KeyCode::D) {
-        move_vector += move_directions.right;
-    }
+    let move_vector = action_axes.move_axis.extend(0.0) * player.speed * time.delta_seconds();
 
     player.acceleration += move_vector;
 }
 
 fn player_phys_update(
     mut player_query: Query<(&mut Transform, &mut Player), With<Player>>,
-    tile_query: Query<
-        (&Transform, &TileCollider),
-        (With<TileCollider>, Without<Player>, Without<EnemyFlock>),
-    >,
+    grid: Res<TileGrid>,
     time: Res<Time>,
 ) {
-    let (mut transform, mut player) = player_query.single_mut();
+    let (mut transform, mut player) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
 
     player.velocity = player.velocity + player.acceleration;
     let friction = player.velocity * -0.01;
-    player.velocity = player.velocity + friction;
+    player.velocity += friction;
     player.velocity = Vec3::clamp_length_max(player.velocity, player.max_speed);
     player.velocity[2] = 0.0;
 
     let wish_pos =
         Vec3::new(player.velocity[0] * time.delta_seconds(), 0.0, 0.0) + transform.translation;
-    if !wall_collision_check(wish_pos, &tile_query) {
+    if !wall_collision_check(wish_pos, &grid) {
         transform.translation = wish_pos;
     } else {
         player.velocity[0] = 0.0;
@@ -132,7 +91,7 @@ fn player_phys_update(
 
     let wish_pos =
         Vec3::new(0.0, player.velocity[1] * time.delta_seconds(), 0.0) + transform.translation;
-    if !wall_collision_check(wish_pos, &tile_query) {
+    if !wall_collision_check(wish_pos, &grid) {
         transform.translation = wish_pos;
     } else {
         player.velocity[1] = 0.0;
@@ -142,41 +101,18 @@ fn player_phys_update(
     transform.translation[2] = 0.0;
 }
 
-pub fn wall_collision_check(
-    target_position: Vec3,
-    wall_query: &Query<
-        (&Transform, &TileCollider),
-        (With<TileCollider>, Without<Player>, Without<EnemyFlock>),
-    >,
-) -> bool {
-    for (wall_transform, wall_collider) in wall_query.iter() {
-        let collision = collide(
-            target_position,
-            Vec2::splat(TILE_SIZE),
-            wall_transform.translation,
-            wall_collider.size,
-        );
-        if collision.is_some() {
-            return true;
-        }
-    }
-    return false;
+pub fn wall_collision_check(target_position: Vec3, grid: &TileGrid) -> bool {
+    grid.collides(target_position, Vec2::splat(TILE_SIZE))
 }
-fn camera_follow(
-    mut camera_query: Query<&mut Transform, (Without<Player>, With<CameraFlag>)>,
-    mut player_query: Query<&Transform, With<Player>>,
+pub fn respawn_player(
+    commands: &mut Commands,
+    ascii: &mut Res<AsciiSheet>,
+    colours: &ColourScheme,
+    run: &RunState,
 ) {
-    let mut camera_transform = camera_query.single_mut();
-    let player_transform = player_query.single_mut();
-
-    camera_transform.translation = player_transform.translation;
-    camera_transform.translation[2] = 600.0;
-}
-
-pub fn respawn_player(mut commands: &mut Commands, ascii: &mut Res<AsciiSheet>, colours: &Res<ColourScheme>) {
     let player = spawn_ascii_sprite(
-        &mut commands,
-        &ascii,
+        commands,
+        ascii,
         3,
         colours.colour_1,
         Vec3::new(0.0, 0.0, 0.0),
@@ -187,154 +123,131 @@ pub fn respawn_player(mut commands: &mut Commands, ascii: &mut Res<AsciiSheet>,
         .insert(Name::new("Player"))
         .insert(Player {
             speed: PLAYER_SPEED,
-            health: PLAYER_HEALTH,
-            shoot_timer: Timer::from_seconds(1.0, false),
-            ammo: STARTING_PLAYER_AMMO,
             velocity: Vec3::splat(0.0),
             acceleration: Vec3::splat(0.0),
             max_speed: PLAYER_MAX_SPEED,
-        });
+            aim_direction: Vec2::X,
+        })
+        .insert(LivingEntity {
+            health: run.health,
+            ..LivingEntity::new(PLAYER_HEALTH).with_invulnerable_time(PLAYER_INVULNERABLE_TIME)
+        })
+        .insert(Weapon::new(&run.weapon));
 }
 
 fn player_exit(
-    mut commands: Commands,
-    ascii: Res<AsciiSheet>,
-    assets: Res<AssetServer>,
-    entity_query: Query<Entity, Without<Manager>>,
-    player_query: Query<&Transform, With<Player>>,
-    exit_query: Query<&Transform, (With<Exit>, Without<Player>)>,
-    colours: Res<ColourScheme>,
-    mut manager_query: Query<&mut Manager, With<Manager>>,
+    player_query: Query<(&Transform, &LivingEntity), With<Player>>,
+    exit_query: Query<&Transform, (With<Exit>, Without<LockedExit>, Without<Player>)>,
+    mut stage_events: EventWriter<StageEvent>,
+    mut run: ResMut<RunState>,
 ) {
-    let player_transform = player_query.single();
-    let exit_transform = exit_query.single();
+    let (player_transform, living) = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    // a boss stage's exit is locked until the boss is dead
+    let exit_transform = match exit_query.get_single() {
+        Ok(exit_transform) => exit_transform,
+        Err(_) => return,
+    };
 
     if Vec3::distance(player_transform.translation, exit_transform.translation) < TILE_SIZE {
-        println!("making new stage");
-        make_new_stage(
-            commands,
-            ascii,
-            entity_query,
-            assets,
-            colours,
-            &mut manager_query,
-        );
+        run.leave_stage(living.health);
+        stage_events.send(StageEvent::Next);
     }
 }
 
 fn player_shoot(
     mut commands: Commands,
-    windows: Res<Windows>,
-    buttons: Res<Input<MouseButton>>,
-    mut player_query: Query<(&Transform, &mut Player), With<Player>>,
-    mut game_manager_query: Query<&mut Manager, With<Manager>>,
-    mut assets: Res<AssetServer>,
+    actions: Res<Input<Action>>,
+    action_axes: Res<ActionAxes>,
+    mut shake_events: EventWriter<ScreenShake>,
+    mut noise_events: EventWriter<NoiseEvent>,
+    mut player_query: Query<(&Transform, &mut Player, &mut Weapon), With<Player>>,
+    mut run: ResMut<RunState>,
+    mut bullet_pool: ResMut<BulletPool>,
+    library: Res<WeaponLibrary>,
+    mut rng: ResMut<StageRng>,
     time: Res<Time>,
 ) {
-    // set all the vars up correctl
-    // there are some problems with this code that leads to crashes when you bring your cursor
-    // outside the window but I can't be bothered to fix that right now
-    let (player_position, mut player) = player_query.single_mut();
-    let mut game_manager = game_manager_query.single_mut();
-    let mut w_width: f32 = 1920.0;
-    let mut w_height: f32 = 1080.0;
-    let mut mouse_position: Vec2 = Vec2::new(w_width / 2.0, w_height / 2.0);
-    for window in windows.iter() {
-        w_width = window.width();
-        w_height = window.height();
-        mouse_position = window.cursor_position().unwrap();
-    }
+    let (player_position, mut player, mut weapon) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
 
-    player.shoot_timer.tick(time.delta());
+    weapon.tick(time.delta_seconds());
 
-    if player.shoot_timer.finished()
-        && buttons.pressed(MouseButton::Left)
-        && game_manager.player_ammo > 0
-    {
-        let mut shoot_vector = Vec3::new(
-            mouse_position[0] - w_width / 2.0,
-            mouse_position[1] - w_height / 2.0,
-            0.0,
-        );
-        shoot_vector = set_magnitude(shoot_vector, 10.0);
+    // the aim is worked out from where the player really is, not the middle of the screen
+    let aim_direction = match action_axes.aim {
+        Some(Aim::Point(point)) => {
+            (point - player_position.translation.truncate()).try_normalize()
+        }
+        Some(Aim::Direction(direction)) => Some(direction),
+        None => None,
+    };
+    if let Some(aim_direction) = aim_direction {
+        player.aim_direction = aim_direction;
+    }
 
-        make_bullet(
+    let weapon_def = match library.get(&weapon.name) {
+        Some(weapon_def) => weapon_def,
+        None => return,
+    };
+    let ammo_before = run.ammo;
+    if weapon.trigger(
+        weapon_def,
+        actions.pressed(Action::Fire),
+        &mut run.ammo,
+    ) {
+        fire_volley(
             &mut commands,
-            &mut assets,
+            &mut bullet_pool,
+            weapon_def,
             player_position.translation,
-            shoot_vector,
+            player.aim_direction,
+            &mut rng.0,
         );
-        player.shoot_timer.reset();
-        game_manager.player_ammo -= 1;
-    }
-}
-// 248~ ascii index
-pub fn make_bullet(
-    mut commands: &mut Commands,
-    mut assets: &mut Res<AssetServer>,
-    spawn_position: Vec3,
-    move_vector: Vec3,
-) {
-    let mut bullet = commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite {
-            custom_size: Some(Vec2::splat(6.0)),
-            ..default()
-        },
-        transform: Transform {
-            translation: spawn_position,
-            ..default()
-        },
-        texture: assets.load("bullet.png"),
-        ..default()
-    });
-    bullet.insert(Bullet { move_vector });
-}
-
-fn update_bullets(
-    mut commands: Commands,
-    mut query: Query<(Entity, &mut Transform, &Bullet), With<Bullet>>,
-    mut enemy_query: Query<(Entity, &Transform), (With<Enemy>, Without<Bullet>)>,
-) {
-    for (bullet, mut transform, bullet_vars) in query.iter_mut() {
-        transform.translation += bullet_vars.move_vector;
-        for (enemy, enemy_transform) in enemy_query.iter_mut() {
-            if Vec3::distance(transform.translation, enemy_transform.translation) < TILE_SIZE {
-                commands.entity(enemy).despawn();
-                commands.entity(bullet).despawn();
-            }
-        }
+        shake_events.send(ScreenShake { trauma: 0.15 });
+        noise_events.send(NoiseEvent {
+            position: player_position.translation,
+            radius: SHOT_NOISE_RADIUS,
+        });
+        run.ammo_used += ammo_before - run.ammo;
     }
 }
-
 fn player_health(
     mut commands: Commands,
-    player_query: Query<(Entity, &Player), With<Player>>,
+    mut death_events: EventReader<DeathEvent>,
+    player_query: Query<Entity, With<Player>>,
     mut state: ResMut<State<GameState>>,
 ) {
-    let (player, player_vars) = player_query.single();
-    if player_vars.health < 1 {
-        state
-            .set(GameState::GameEnd)
-            .expect("Failed to change gamestate.");
-        commands.entity(player).despawn();
+    for death in death_events.iter() {
+        if player_query.get(death.entity).is_ok() {
+            state
+                .set(GameState::GameEnd)
+                .expect("Failed to change gamestate.");
+            commands.entity(death.entity).despawn();
+        }
     }
 }
 
 fn player_ammo_check(
     mut commands: Commands,
-    mut player_query: Query<(&Transform, &mut Player), With<Player>>,
+    player_query: Query<&Transform, With<Player>>,
     mut fuel_query: Query<(Entity, &Transform), (With<Ammo>, Without<Player>)>,
-    mut manager_query: Query<&mut Manager, With<Manager>>,
+    mut run: ResMut<RunState>,
 ) {
-    let (player_transform, mut player) = player_query.single_mut();
-    let mut manager = manager_query.single_mut();
+    let player_transform = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
 
     for (fuel, fuel_transform) in fuel_query.iter_mut() {
         if Vec3::distance(player_transform.translation, fuel_transform.translation)
             < TILE_SIZE * 0.98
         {
             commands.entity(fuel).despawn();
-            manager.player_ammo += 3;
+            run.ammo += 3;
         }
     }
 }
@@ -0,0 +1,199 @@
+use bevy::prelude::*;
+
+use crate::{player::PLAYER_HEALTH, seed::new_run_seed, weapon::STARTING_WEAPON, GameState};
+
+const STARTING_AMMO: i32 = 3;
+const KILL_SCORE: i32 = 10;
+const STAGE_SCORE: i32 = 100;
+// every this many stages ends in an arena with a boss in it
+const BOSS_STAGE_INTERVAL: i32 = 5;
+
+pub struct RunPlugin;
+
+impl Plugin for RunPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(RunState::new(new_run_seed()))
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(track_run_time));
+    }
+}
+
+// everything about the current run that outlives a stage. make_new_stage despawns every
+// entity, so anything that isn't kept in here starts again with each stage
+#[derive(Clone, PartialEq, Debug)]
+pub struct RunState {
+    // the seed the whole run is generated from, pass --seed <number> to replay a run
+    pub seed: u64,
+    pub stage_number: i32,
+    pub difficulty_coefficient: f32,
+    // the player's health as they left the last stage, during a stage it's on their
+    // LivingEntity
+    pub health: f32,
+    pub ammo: i32,
+    // name of the weapon the player carries from stage to stage
+    pub weapon: String,
+    pub score: i32,
+    // stats for the game over screen
+    pub kills: i32,
+    pub ammo_used: i32,
+    pub run_time: f32,
+    // the carried over fields as they were when the current stage began
+    stage_start: Carried,
+}
+
+// what restarting a stage puts back. the seed and stage number don't change within a stage
+// and the run time keeps counting
+#[derive(Clone, PartialEq, Debug)]
+struct Carried {
+    health: f32,
+    ammo: i32,
+    weapon: String,
+    score: i32,
+    kills: i32,
+    ammo_used: i32,
+}
+
+impl RunState {
+    // the state at the start of a run
+    pub fn new(seed: u64) -> RunState {
+        let stage_start = Carried {
+            health: PLAYER_HEALTH,
+            ammo: STARTING_AMMO,
+            weapon: STARTING_WEAPON.to_string(),
+            score: 0,
+            kills: 0,
+            ammo_used: 0,
+        };
+        let mut run = RunState {
+            seed,
+            stage_number: 1,
+            difficulty_coefficient: 0.1,
+            health: 0.0,
+            ammo: 0,
+            weapon: String::new(),
+            score: 0,
+            kills: 0,
+            ammo_used: 0,
+            run_time: 0.0,
+            stage_start,
+        };
+        run.restart_stage();
+        run
+    }
+
+    // the player reached the exit with this much health left
+    pub fn leave_stage(&mut self, health: f32) {
+        self.health = health;
+        self.score += STAGE_SCORE;
+    }
+
+    // everything carries over into the next stage as it is now
+    pub fn next_stage(&mut self) {
+        self.stage_number += 1;
+        self.stage_start = self.carried();
+    }
+
+    // undoes everything that happened since the current stage began
+    pub fn restart_stage(&mut self) {
+        let start = self.stage_start.clone();
+        self.health = start.health;
+        self.ammo = start.ammo;
+        self.weapon = start.weapon;
+        self.score = start.score;
+        self.kills = start.kills;
+        self.ammo_used = start.ammo_used;
+    }
+
+    // the first stage of a run is stage_number 2, it gets bumped before the stage is made
+    pub fn is_boss_stage(&self) -> bool {
+        self.stage_number > 1 && (self.stage_number - 1) % BOSS_STAGE_INTERVAL == 0
+    }
+
+    pub fn add_kill(&mut self) {
+        self.kills += 1;
+        self.score += KILL_SCORE;
+    }
+
+    fn carried(&self) -> Carried {
+        Carried {
+            health: self.health,
+            ammo: self.ammo,
+            weapon: self.weapon.clone(),
+            score: self.score,
+            kills: self.kills,
+            ammo_used: self.ammo_used,
+        }
+    }
+}
+
+fn track_run_time(mut run: ResMut<RunState>, time: Res<Time>) {
+    run.run_time += time.delta_seconds();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // partway through a stage, with everything different from how it started
+    fn mid_stage(run: &mut RunState) {
+        run.health = 1.5;
+        run.ammo = 17;
+        run.weapon = "not the starting weapon".to_string();
+        run.add_kill();
+        run.ammo_used += 4;
+    }
+
+    #[test]
+    fn restart_puts_back_the_stage_start() {
+        let mut run = RunState::new(7);
+        run.next_stage();
+        let start = run.clone();
+
+        mid_stage(&mut run);
+        run.run_time = 30.0;
+        run.restart_stage();
+
+        assert_eq!(run.health, start.health);
+        assert_eq!(run.ammo, start.ammo);
+        assert_eq!(run.weapon, start.weapon);
+        assert_eq!((run.score, run.kills, run.ammo_used), (0, 0, 0));
+        assert_eq!(run.stage_number, start.stage_number);
+        assert_eq!(run.run_time, 30.0);
+    }
+
+    #[test]
+    fn next_stage_carries_everything_forward() {
+        let mut run = RunState::new(7);
+        run.next_stage();
+        mid_stage(&mut run);
+        run.leave_stage(2.5);
+        run.next_stage();
+        let carried = run.clone();
+
+        assert_eq!(run.stage_number, 3);
+        assert_eq!(run.health, 2.5);
+        assert_eq!(run.score, KILL_SCORE + STAGE_SCORE);
+
+        // restarting the new stage goes back to how the last one was left
+        run.ammo = 0;
+        run.add_kill();
+        run.restart_stage();
+        assert_eq!(run, carried);
+    }
+
+    #[test]
+    fn boss_every_interval() {
+        let mut run = RunState::new(7);
+        let mut boss_stages = Vec::new();
+        for _ in 0..BOSS_STAGE_INTERVAL * 2 {
+            run.next_stage();
+            if run.is_boss_stage() {
+                boss_stages.push(run.stage_number);
+            }
+        }
+        // the stage number is one ahead of how many stages have been played
+        assert_eq!(
+            boss_stages,
+            vec![BOSS_STAGE_INTERVAL + 1, BOSS_STAGE_INTERVAL * 2 + 1]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use bevy::prelude::*;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{components::SeedText, run::RunState};
+
+pub struct SeedPlugin;
+
+impl Plugin for SeedPlugin {
+    fn build(&self, app: &mut App) {
+        // reseeded from the run before every stage, this one never gets used
+        app.insert_resource(StageRng::for_stage(0, 0))
+            .add_system(update_seed_text);
+    }
+}
+
+// every random roll made while building or playing a stage comes out of this, it gets reseeded
+// from the run seed and the stage number each time a stage is made so stages don't depend on
+// each other
+pub struct StageRng(pub StdRng);
+
+impl StageRng {
+    pub fn for_stage(seed: u64, stage_number: i32) -> StageRng {
+        StageRng(StdRng::seed_from_u64(mix_seed(seed, stage_number)))
+    }
+}
+
+// splitmix64 finaliser, keeps neighbouring stage numbers from giving similar streams
+fn mix_seed(seed: u64, stage_number: i32) -> u64 {
+    let mut z = seed ^ (stage_number as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// the seed passed with --seed, so restarting replays the same run, or a random one
+pub fn new_run_seed() -> u64 {
+    let seed = seed_from_args(std::env::args()).unwrap_or_else(|| rand::thread_rng().gen());
+    println!("run seed: {}", seed);
+    seed
+}
+
+// accepts both "--seed 1234" and "--seed=1234"
+pub fn seed_from_args(mut args: impl Iterator<Item = String>) -> Option<u64> {
+    while let Some(arg) = args.next() {
+        let value = if arg == "--seed" {
+            args.next()
+        } else if let Some(value) = arg.strip_prefix("--seed=") {
+            Some(value.to_string())
+        } else {
+            continue;
+        };
+
+        return match value.as_deref().map(str::parse::<u64>) {
+            Some(Ok(seed)) => Some(seed),
+            _ => {
+                println!("--seed needs a whole number, using a random seed instead");
+                None
+            }
+        };
+    }
+    None
+}
+
+fn update_seed_text(mut query: Query<&mut Text, With<SeedText>>, run: Res<RunState>) {
+    for mut seed_text in query.iter_mut() {
+        seed_text.sections[1].value = format!("{}", run.seed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+    use crate::{
+        ascii::AsciiSheet,
+        colourscheme::generate_colourscheme,
+        components::Enemy,
+        enemydef,
+        layout::{generate_layout, BlockSource, LayoutSize},
+        mapblock,
+        tilemap::{generate_map, StageContent},
+    };
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(str::to_string)
+    }
+
+    fn first_rolls(seed: u64, stage_number: i32) -> Vec<u64> {
+        let mut rng = StageRng::for_stage(seed, stage_number);
+        (0..8).map(|_| rng.0.gen()).collect()
+    }
+
+    // where each block went and which library block it is
+    fn layout(seed: u64, stage_number: i32) -> Vec<(i32, i32, Option<usize>, bool)> {
+        let library = mapblock::test_library();
+        let mut rng = StageRng::for_stage(seed, stage_number);
+        let size = LayoutSize::for_stage(stage_number);
+        generate_layout(&library, &size, library.exit, None, &mut rng.0)
+            .unwrap()
+            .iter()
+            .map(|block| {
+                let index = match block.block {
+                    BlockSource::Library(index) => Some(index),
+                    BlockSource::Generated(_) => None,
+                };
+                (block.x, block.y, index, block.exit)
+            })
+            .collect()
+    }
+
+    // builds the stage the way make_new_stage does and lists every enemy put on it
+    fn enemies(seed: u64, stage_number: i32) -> Vec<(String, Vec3)> {
+        let mut world = World::new();
+        world.insert_resource(AsciiSheet::default());
+        let mut run = RunState::new(seed);
+        run.stage_number = stage_number;
+        let library = mapblock::test_library();
+        let enemies = enemydef::test_library();
+
+        let mut system_state: SystemState<(Commands, Res<AsciiSheet>)> =
+            SystemState::new(&mut world);
+        let (mut commands, ascii) = system_state.get_mut(&mut world);
+        let mut rng = StageRng::for_stage(run.seed, run.stage_number);
+        let colours = generate_colourscheme(&mut commands, &mut rng.0);
+        generate_map(
+            &mut commands,
+            &ascii,
+            &StageContent {
+                library: &library,
+                enemies: &enemies,
+                wfc: None,
+                colours: &colours,
+                run: &run,
+            },
+            &mut rng.0,
+        );
+        system_state.apply(&mut world);
+
+        world
+            .query_filtered::<(&Name, &Transform), With<Enemy>>()
+            .iter(&world)
+            .map(|(name, transform)| (name.to_string(), transform.translation))
+            .collect()
+    }
+
+    #[test]
+    fn same_stage_same_rolls() {
+        assert_eq!(first_rolls(1234, 3), first_rolls(1234, 3));
+    }
+
+    #[test]
+    fn different_stages_different_rolls() {
+        assert_ne!(first_rolls(1234, 3), first_rolls(1234, 4));
+        assert_ne!(first_rolls(1234, 3), first_rolls(1235, 3));
+    }
+
+    #[test]
+    fn same_stage_same_layout() {
+        for stage_number in 1..6 {
+            assert_eq!(layout(99, stage_number), layout(99, stage_number));
+        }
+        assert_ne!(layout(99, 4), layout(100, 4));
+    }
+
+    #[test]
+    fn same_stage_same_enemies() {
+        let first = enemies(99, 8);
+        assert!(!first.is_empty());
+        assert_eq!(first, enemies(99, 8));
+    }
+
+    #[test]
+    fn seed_flag_with_space() {
+        assert_eq!(seed_from_args(args("piko --seed 5")), Some(5));
+    }
+
+    #[test]
+    fn seed_flag_with_equals() {
+        assert_eq!(seed_from_args(args("piko --seed=5")), Some(5));
+    }
+
+    #[test]
+    fn seed_flag_not_a_number() {
+        assert_eq!(seed_from_args(args("piko --seed abc")), None);
+        assert_eq!(seed_from_args(args("piko --seed=-5")), None);
+    }
+
+    #[test]
+    fn seed_flag_missing_value() {
+        assert_eq!(seed_from_args(args("piko --seed")), None);
+        assert_eq!(seed_from_args(args("piko --seed=")), None);
+    }
+
+    #[test]
+    fn no_seed_flag() {
+        assert_eq!(seed_from_args(args("piko")), None);
+        assert_eq!(seed_from_args(args("piko --seeds 5")), None);
+    }
+}
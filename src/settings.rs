@@ -0,0 +1,493 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    prelude::*,
+    window::{PresentMode, WindowMode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::Action,
+    components::SettingsMenuFlag,
+    menu::{menu_input, spawn_menu, MenuCursor, MenuItem},
+    GameState,
+};
+
+const GAME_FOLDER: &str = "piko";
+const SETTINGS_FILE: &str = "settings.ron";
+
+const RESOLUTIONS: [(f32, f32); 5] = [
+    (1280.0, 720.0),
+    (1366.0, 768.0),
+    (1600.0, 900.0),
+    (1920.0, 1080.0),
+    (2560.0, 1440.0),
+];
+const UI_SCALES: [f32; 5] = [0.75, 1.0, 1.25, 1.5, 2.0];
+const VOLUME_STEP: f32 = 0.1;
+
+// the rows of the settings screen, in the order they're shown
+const WINDOW_MODE_OPTION: usize = 0;
+const RESOLUTION_OPTION: usize = 1;
+const VSYNC_OPTION: usize = 2;
+const UI_SCALE_OPTION: usize = 3;
+const MASTER_VOLUME_OPTION: usize = 4;
+const MUSIC_VOLUME_OPTION: usize = 5;
+const EFFECTS_VOLUME_OPTION: usize = 6;
+const MOVE_UP_OPTION: usize = 7;
+const MOVE_DOWN_OPTION: usize = 8;
+const MOVE_LEFT_OPTION: usize = 9;
+const MOVE_RIGHT_OPTION: usize = 10;
+const PAUSE_OPTION: usize = 11;
+const FIRE_KEY_OPTION: usize = 12;
+const SHOOT_OPTION: usize = 13;
+const RESET_BINDINGS_OPTION: usize = 14;
+const BACK_OPTION: usize = 15;
+const OPTION_COUNT: usize = 16;
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        // the Settings resource itself is inserted in main, the window is made from it
+        app.add_system(apply_settings)
+            .add_system_set(
+                SystemSet::on_enter(GameState::Settings).with_system(settings_menu_setup),
+            )
+            .add_system_set(SystemSet::on_update(GameState::Settings).with_system(settings_menu))
+            .add_system_set(
+                SystemSet::on_exit(GameState::Settings)
+                    .with_system(despawn_settings_menu)
+                    .with_system(save_settings),
+            );
+    }
+}
+
+// bevy's WindowMode can't be serialized so the file uses its own copy
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WindowModeSetting {
+    Windowed,
+    BorderlessFullscreen,
+    Fullscreen,
+}
+
+impl WindowModeSetting {
+    pub fn window_mode(self) -> WindowMode {
+        match self {
+            WindowModeSetting::Windowed => WindowMode::Windowed,
+            WindowModeSetting::BorderlessFullscreen => WindowMode::BorderlessFullscreen,
+            WindowModeSetting::Fullscreen => WindowMode::Fullscreen,
+        }
+    }
+
+    fn next(self) -> WindowModeSetting {
+        match self {
+            WindowModeSetting::Windowed => WindowModeSetting::BorderlessFullscreen,
+            WindowModeSetting::BorderlessFullscreen => WindowModeSetting::Fullscreen,
+            WindowModeSetting::Fullscreen => WindowModeSetting::Windowed,
+        }
+    }
+
+    fn previous(self) -> WindowModeSetting {
+        self.next().next()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub pause: KeyCode,
+    // a key as well as a mouse button so the game can be played on the keyboard alone
+    pub fire_key: KeyCode,
+    pub shoot: MouseButton,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            up: KeyCode::W,
+            down: KeyCode::S,
+            left: KeyCode::A,
+            right: KeyCode::D,
+            pause: KeyCode::Escape,
+            fire_key: KeyCode::Space,
+            shoot: MouseButton::Left,
+        }
+    }
+}
+
+// everything the player can change, kept in settings.ron in the user's config folder.
+// fields missing from the file get their default so older files still load
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Settings {
+    pub window_mode: WindowModeSetting,
+    pub resolution: (f32, f32),
+    pub vsync: bool,
+    pub ui_scale: f32,
+    // there's no audio yet, the volumes are stored so they're ready when there is
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub effects_volume: f32,
+    pub key_bindings: KeyBindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            window_mode: WindowModeSetting::Fullscreen,
+            resolution: (1920.0, 1080.0),
+            vsync: true,
+            ui_scale: 1.0,
+            master_volume: 1.0,
+            music_volume: 1.0,
+            effects_volume: 1.0,
+            key_bindings: KeyBindings::default(),
+        }
+    }
+}
+
+impl Settings {
+    // never fails, anything wrong with the file is reported and the defaults are used instead
+    pub fn load() -> Settings {
+        let path = match settings_path() {
+            Some(path) => path,
+            None => {
+                println!("warning: couldn't find a config folder, using the default settings");
+                return Settings::default();
+            }
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) => {
+                println!(
+                    "warning: couldn't read {}: {}, using the default settings",
+                    path.display(),
+                    error
+                );
+                return Settings::default();
+            }
+        };
+        Settings::parse(&path, &contents)
+    }
+
+    fn parse(path: &Path, contents: &str) -> Settings {
+        match ron::from_str(contents) {
+            Ok(settings) => settings,
+            Err(error) => {
+                println!(
+                    "warning: {} is corrupt: {}, using the default settings",
+                    path.display(),
+                    error
+                );
+                Settings::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let path = match settings_path() {
+            Some(path) => path,
+            None => {
+                println!("warning: couldn't find a config folder, the settings weren't saved");
+                return;
+            }
+        };
+        let contents = match ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) {
+            Ok(contents) => contents,
+            Err(error) => {
+                println!("warning: couldn't save the settings: {}", error);
+                return;
+            }
+        };
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, contents));
+        if let Err(error) = written {
+            println!("warning: couldn't write {}: {}", path.display(), error);
+        }
+    }
+
+    pub fn window_descriptor(&self) -> WindowDescriptor {
+        WindowDescriptor {
+            title: "Piko".to_string(),
+            width: self.resolution.0,
+            height: self.resolution.1,
+            mode: self.window_mode.window_mode(),
+            present_mode: self.present_mode(),
+            ..default()
+        }
+    }
+
+    fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        }
+    }
+
+    // Confirm and Right step a setting forward, Left steps it back
+    fn change(&mut self, option: usize, forward: bool) {
+        match option {
+            WINDOW_MODE_OPTION => {
+                self.window_mode = if forward {
+                    self.window_mode.next()
+                } else {
+                    self.window_mode.previous()
+                }
+            }
+            RESOLUTION_OPTION => self.resolution = step(&RESOLUTIONS, self.resolution, forward),
+            VSYNC_OPTION => self.vsync = !self.vsync,
+            UI_SCALE_OPTION => self.ui_scale = step(&UI_SCALES, self.ui_scale, forward),
+            MASTER_VOLUME_OPTION => step_volume(&mut self.master_volume, forward),
+            MUSIC_VOLUME_OPTION => step_volume(&mut self.music_volume, forward),
+            EFFECTS_VOLUME_OPTION => step_volume(&mut self.effects_volume, forward),
+            _ => {}
+        }
+    }
+
+    fn labels(&self) -> Vec<String> {
+        let bindings = &self.key_bindings;
+        let mut labels = vec![String::new(); OPTION_COUNT];
+        labels[WINDOW_MODE_OPTION] = format!("Window mode: {:?}", self.window_mode);
+        labels[RESOLUTION_OPTION] =
+            format!("Resolution: {}x{}", self.resolution.0, self.resolution.1);
+        labels[VSYNC_OPTION] = format!("VSync: {}", if self.vsync { "On" } else { "Off" });
+        labels[UI_SCALE_OPTION] = format!("UI scale: {}x", self.ui_scale);
+        labels[MASTER_VOLUME_OPTION] = volume_label("Master volume", self.master_volume);
+        labels[MUSIC_VOLUME_OPTION] = volume_label("Music volume", self.music_volume);
+        labels[EFFECTS_VOLUME_OPTION] = volume_label("Effects volume", self.effects_volume);
+        labels[MOVE_UP_OPTION] = format!("Move up: {:?}", bindings.up);
+        labels[MOVE_DOWN_OPTION] = format!("Move down: {:?}", bindings.down);
+        labels[MOVE_LEFT_OPTION] = format!("Move left: {:?}", bindings.left);
+        labels[MOVE_RIGHT_OPTION] = format!("Move right: {:?}", bindings.right);
+        labels[PAUSE_OPTION] = format!("Pause: {:?}", bindings.pause);
+        labels[FIRE_KEY_OPTION] = format!("Fire key: {:?}", bindings.fire_key);
+        labels[SHOOT_OPTION] = format!("Fire mouse: {:?}", bindings.shoot);
+        labels[RESET_BINDINGS_OPTION] = "Reset key bindings".to_string();
+        labels[BACK_OPTION] = "Back".to_string();
+        labels
+    }
+
+    fn key_binding_mut(&mut self, option: usize) -> Option<&mut KeyCode> {
+        let bindings = &mut self.key_bindings;
+        match option {
+            MOVE_UP_OPTION => Some(&mut bindings.up),
+            MOVE_DOWN_OPTION => Some(&mut bindings.down),
+            MOVE_LEFT_OPTION => Some(&mut bindings.left),
+            MOVE_RIGHT_OPTION => Some(&mut bindings.right),
+            PAUSE_OPTION => Some(&mut bindings.pause),
+            FIRE_KEY_OPTION => Some(&mut bindings.fire_key),
+            _ => None,
+        }
+    }
+}
+
+// moves to the next or previous entry of a list of choices. a value that isn't in the list,
+// from a hand edited file, goes to the start
+fn step<T: PartialEq + Copy>(choices: &[T], current: T, forward: bool) -> T {
+    let index = match choices.iter().position(|choice| *choice == current) {
+        Some(index) if forward => (index + 1) % choices.len(),
+        Some(index) => (index + choices.len() - 1) % choices.len(),
+        None => 0,
+    };
+    choices[index]
+}
+
+fn step_volume(volume: &mut f32, forward: bool) {
+    let step = if forward { VOLUME_STEP } else { -VOLUME_STEP };
+    // rounded so repeated steps don't drift away from whole percentages
+    *volume = ((*volume + step).clamp(0.0, 1.0) * 10.0).round() / 10.0;
+}
+
+fn volume_label(name: &str, volume: f32) -> String {
+    format!("{}: {}%", name, (volume * 100.0).round() as i32)
+}
+
+fn settings_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(GAME_FOLDER).join(SETTINGS_FILE))
+}
+
+// where each platform expects programs to keep their settings
+fn config_dir() -> Option<PathBuf> {
+    let from_env = |name: &str| env::var_os(name).filter(|value| !value.is_empty());
+    if cfg!(target_os = "windows") {
+        from_env("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        from_env("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        from_env("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| from_env("HOME").map(|home| PathBuf::from(home).join(".config")))
+    }
+}
+
+// keeps the window in step with the settings, the ui text scales itself in ui.rs
+fn apply_settings(settings: Res<Settings>, mut windows: ResMut<Windows>) {
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_mode(settings.window_mode.window_mode());
+        window.set_resolution(settings.resolution.0, settings.resolution.1);
+        window.set_present_mode(settings.present_mode());
+    }
+}
+
+fn save_settings(settings: Res<Settings>) {
+    settings.save();
+}
+
+fn settings_menu_setup(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    settings: Res<Settings>,
+    mut cursor: ResMut<MenuCursor>,
+) {
+    cursor.0 = 0;
+    for entity in spawn_menu(&mut commands, &assets, "Settings", &settings.labels()) {
+        commands.entity(entity).insert(SettingsMenuFlag);
+    }
+}
+
+fn despawn_settings_menu(
+    mut commands: Commands,
+    menu_query: Query<Entity, With<SettingsMenuFlag>>,
+) {
+    for entity in menu_query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+// which binding is waiting for its new key or button, if any
+#[derive(Default)]
+struct Rebinding(Option<usize>);
+
+fn settings_menu(
+    mut actions: ResMut<Input<Action>>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut buttons: ResMut<Input<MouseButton>>,
+    mut cursor: ResMut<MenuCursor>,
+    mut state: ResMut<State<GameState>>,
+    mut settings: ResMut<Settings>,
+    mut rebinding: Local<Rebinding>,
+    mut item_query: Query<(&MenuItem, &mut Text)>,
+) {
+    if let Some(option) = rebinding.0 {
+        let pressed_key = keys.get_just_pressed().next().copied();
+        let pressed_button = buttons.get_just_pressed().next().copied();
+        // Escape always cancels so a binding can't be lost to a key that can't be pressed.
+        // resetting the bindings is how Pause gets back onto Escape
+        if keys.clear_just_pressed(KeyCode::Escape) {
+            rebinding.0 = None;
+        } else if option == SHOOT_OPTION {
+            if let Some(button) = pressed_button {
+                buttons.clear_just_pressed(button);
+                settings.key_bindings.shoot = button;
+                rebinding.0 = None;
+            }
+        } else if let Some(key) = pressed_key {
+            keys.clear_just_pressed(key);
+            if let Some(binding) = settings.key_binding_mut(option) {
+                *binding = key;
+            }
+            rebinding.0 = None;
+        }
+        if rebinding.0.is_none() {
+            relabel(&settings, &mut item_query);
+        }
+        return;
+    }
+
+    if actions.clear_just_pressed(Action::Cancel) {
+        // Escape is both Cancel and Pause, the pause menu underneath mustn't close on the
+        // same press
+        actions.clear_just_pressed(Action::Pause);
+        state.pop().expect("Failed to change game state.");
+        return;
+    }
+
+    let stepped = if actions.clear_just_pressed(Action::Right) {
+        Some(true)
+    } else if actions.clear_just_pressed(Action::Left) {
+        Some(false)
+    } else {
+        None
+    };
+    if let Some(forward) = stepped {
+        settings.change(cursor.0, forward);
+        relabel(&settings, &mut item_query);
+        return;
+    }
+
+    match menu_input(&mut actions, &mut cursor, OPTION_COUNT) {
+        Some(BACK_OPTION) => state.pop().expect("Failed to change game state."),
+        Some(option) if (MOVE_UP_OPTION..=SHOOT_OPTION).contains(&option) => {
+            rebinding.0 = Some(option);
+            let prompt = if option == SHOOT_OPTION {
+                "Press a mouse button, Escape to cancel"
+            } else {
+                "Press a key, Escape to cancel"
+            };
+            for (item, mut text) in item_query.iter_mut() {
+                if item.index == option {
+                    text.sections[0].value = prompt.to_string();
+                }
+            }
+        }
+        Some(RESET_BINDINGS_OPTION) => {
+            settings.key_bindings = KeyBindings::default();
+            relabel(&settings, &mut item_query);
+        }
+        Some(option) => {
+            settings.change(option, true);
+            relabel(&settings, &mut item_query);
+        }
+        None => {}
+    }
+}
+
+fn relabel(settings: &Settings, item_query: &mut Query<(&MenuItem, &mut Text)>) {
+    let labels = settings.labels();
+    for (item, mut text) in item_query.iter_mut() {
+        text.sections[0].value = labels[item.index].clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Settings {
+        Settings::parse(Path::new(SETTINGS_FILE), contents)
+    }
+
+    #[test]
+    fn corrupt_files_give_the_defaults() {
+        assert_eq!(
+            parse("(window_mode: Windowed, vsync: fa"),
+            Settings::default()
+        );
+        assert_eq!(parse("(vsync: 12)"), Settings::default());
+        assert_eq!(parse("not ron at all {{"), Settings::default());
+        assert_eq!(parse(""), Settings::default());
+    }
+
+    #[test]
+    fn missing_fields_get_their_defaults() {
+        let settings = parse("(vsync: false, key_bindings: (pause: P))");
+        assert!(!settings.vsync);
+        assert_eq!(settings.key_bindings.pause, KeyCode::P);
+        assert_eq!(settings.key_bindings.up, KeyCode::W);
+        assert_eq!(settings.resolution, Settings::default().resolution);
+    }
+}
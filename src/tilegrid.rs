@@ -0,0 +1,143 @@
+use bevy::{prelude::*, sprite::collide_aabb::collide};
+
+use crate::TILE_SIZE;
+
+// tiles are packed slightly closer than their size so walls don't show seams
+pub const TILE_STEP: f32 = TILE_SIZE * 0.98;
+const TILE_ORIGIN: f32 = (32 / 2) as f32 * TILE_SIZE;
+
+// world position in tile units, tile centres land on whole numbers
+pub fn tile_position(translation: Vec3) -> Vec2 {
+    (translation.truncate() - Vec2::splat(TILE_ORIGIN)) / TILE_STEP
+}
+
+// converts a world position to the coordinates of the tile it sits on
+pub fn tile_coords(translation: Vec3) -> IVec2 {
+    tile_position(translation).round().as_ivec2()
+}
+
+// world position of the centre of a tile, on the z = 0 plane
+pub fn tile_translation(coords: IVec2) -> Vec3 {
+    (coords.as_vec2() * TILE_STEP + Vec2::splat(TILE_ORIGIN)).extend(0.0)
+}
+
+// which tiles of the current stage are walls, filled in by draw_map_blocks.
+// the long border colliders don't fit the grid so they are kept as plain boxes
+#[derive(Default)]
+pub struct TileGrid {
+    origin: IVec2,
+    size: IVec2,
+    walls: Vec<bool>,
+    borders: Vec<(Vec3, Vec2)>,
+}
+
+impl TileGrid {
+    pub fn new(wall_tiles: &[IVec2], borders: Vec<(Vec3, Vec2)>) -> TileGrid {
+        let mut corners: Vec<IVec2> = wall_tiles.to_vec();
+        for (translation, size) in borders.iter() {
+            let half_size = size.extend(0.0) / 2.0;
+            corners.push(tile_coords(*translation - half_size));
+            corners.push(tile_coords(*translation + half_size));
+        }
+        if corners.is_empty() {
+            return TileGrid::default();
+        }
+
+        let mut min = corners[0];
+        let mut max = corners[0];
+        for corner in corners.iter() {
+            min = min.min(*corner);
+            max = max.max(*corner);
+        }
+
+        let mut grid = TileGrid {
+            origin: min,
+            size: max - min + IVec2::ONE,
+            walls: Vec::new(),
+            borders,
+        };
+        grid.walls = vec![false; (grid.size.x * grid.size.y) as usize];
+        for tile in wall_tiles.iter() {
+            let index = grid.index(*tile).unwrap();
+            grid.walls[index] = true;
+        }
+        grid
+    }
+
+    // lowest and highest tile coordinates covered by the grid
+    pub fn bounds(&self) -> (IVec2, IVec2) {
+        (self.origin, self.origin + self.size - IVec2::ONE)
+    }
+
+    fn index(&self, coords: IVec2) -> Option<usize> {
+        let local = coords - self.origin;
+        if local.x < 0 || local.y < 0 || local.x >= self.size.x || local.y >= self.size.y {
+            return None;
+        }
+        Some((local.x * self.size.y + local.y) as usize)
+    }
+
+    pub fn is_wall(&self, coords: IVec2) -> bool {
+        self.index(coords).is_some_and(|index| self.walls[index])
+    }
+
+    // a wall tile, or a tile the centre of which is covered by one of the borders
+    pub fn is_solid(&self, coords: IVec2) -> bool {
+        if self.is_wall(coords) {
+            return true;
+        }
+        let centre = tile_translation(coords);
+        self.borders.iter().any(|(translation, size)| {
+            let offset = (centre - *translation).truncate().abs();
+            offset.x <= size.x / 2.0 && offset.y <= size.y / 2.0
+        })
+    }
+
+    // does a box of this size at this position overlap any wall
+    pub fn collides(&self, position: Vec3, size: Vec2) -> bool {
+        let reach = (size + Vec2::splat(TILE_SIZE)).extend(0.0) / 2.0;
+        let min = tile_coords(position - reach);
+        let max = tile_coords(position + reach);
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                let coords = IVec2::new(x, y);
+                if self.is_wall(coords)
+                    && collide(
+                        position,
+                        size,
+                        tile_translation(coords),
+                        Vec2::splat(TILE_SIZE),
+                    )
+                    .is_some()
+                {
+                    return true;
+                }
+            }
+        }
+
+        self.borders.iter().any(|(translation, border_size)| {
+            collide(position, size, *translation, *border_size).is_some()
+        })
+    }
+
+    // centres of the wall tiles within radius of a position
+    pub fn walls_near(&self, position: Vec3, radius: f32) -> Vec<Vec3> {
+        let reach = Vec3::new(radius, radius, 0.0);
+        let min = tile_coords(position - reach);
+        let max = tile_coords(position + reach);
+        let mut walls = Vec::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                let coords = IVec2::new(x, y);
+                if !self.is_wall(coords) {
+                    continue;
+                }
+                let translation = tile_translation(coords);
+                if translation.truncate().distance(position.truncate()) < radius {
+                    walls.push(translation);
+                }
+            }
+        }
+        walls
+    }
+}
@@ -1,356 +1,243 @@
-use std::{
-    env,
-    fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
-};
-
-use glob::{glob_with, MatchOptions};
-
-use rand::prelude::*;
+use rand::{prelude::*, rngs::StdRng};
 
 use bevy::prelude::*;
 
 use crate::{
+    ai::{patrol_route, AiBrain},
     ascii::{spawn_ascii_sprite, AsciiSheet},
+    camera::CameraBounds,
     colourscheme::ColourScheme,
-    components::{Ammo, Exit, Manager, TileCollider},
-    gameobject::spawn_runner_enemy,
-    make_new_stage,
-    player::make_bullet,
+    components::{Ammo, Exit, LockedExit, TileCollider, WeaponPickup},
+    enemydef::EnemyLibrary,
+    gameobject::spawn_enemy,
+    layout::{fallback_layout, generate_layout, layout_bounds, LayoutSize, MapBlock},
+    legend::{LegendEntry, SpawnChance, TileComponent},
+    mapblock::{MapBlockLibrary, MAP_BLOCK_TILES},
+    run::RunState,
+    tilegrid::{tile_translation, TileGrid, TILE_STEP},
+    wfc::WfcModel,
     TILE_SIZE,
 };
 
-pub const MAP_BLOCK_X: f32 = 32.0 * TILE_SIZE;
-pub const MAP_BLOCK_Y: f32 = 32.0 * TILE_SIZE;
-
 pub struct TileMapPlugin;
 
 impl Plugin for TileMapPlugin {
-    fn build(&self, app: &mut App) {}
-}
-
-fn map_gen_manager() {}
-
-pub struct MapBlock {
-    x: i32,
-    y: i32,
-    block_id: String,
-    entrance: bool,
-    exit: bool,
-    tiles: Vec<Entity>,
-}
-
-fn random_map_id(id_list: &Vec<String>) -> String {
-    let mut rng = rand::thread_rng();
-    return id_list.choose(&mut rng).unwrap().to_string();
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TileGrid>();
+    }
 }
 
-fn first_map_gen(
-    mut commands: Commands,
-    mut ascii: Res<AsciiSheet>,
-    mut assets: Res<AssetServer>,
-    mut manager_query: Query<&mut Manager, With<Manager>>,
-    colours: Res<ColourScheme>,
-) {
-    generate_map(
-        &mut commands,
-        &mut ascii,
-        &mut assets,
-        &colours,
-        &mut manager_query,
-    );
+// everything a stage is made from, apart from the rng
+pub struct StageContent<'a> {
+    pub library: &'a MapBlockLibrary,
+    pub enemies: &'a EnemyLibrary,
+    pub wfc: Option<&'a WfcModel>,
+    pub colours: &'a ColourScheme,
+    pub run: &'a RunState,
 }
 
 // simplified map generation system because the last one was ridiculous
 pub fn generate_map(
-    mut commands: &mut Commands,
-    mut ascii: &mut Res<AsciiSheet>,
-    mut assets: &mut Res<AssetServer>,
-    colours: &Res<ColourScheme>,
-    mut manager_query: &mut Query<&mut Manager, With<Manager>>,
-    // manager_query: Query<&Manager, With<Manager>>,
+    commands: &mut Commands,
+    ascii: &AsciiSheet,
+    content: &StageContent,
+    rng: &mut StdRng,
 ) {
-    make_bullet(
-        &mut commands,
-        &mut assets,
-        Vec3::splat(TILE_SIZE * 1000.0),
-        Vec3::splat(0.0),
-    );
-
-    let mut rng = thread_rng();
-
-    let map_size = 2;
-    let mut map_blocks: Vec<MapBlock> = Vec::new();
-    let mut potential_exits: Vec<MapBlock> = Vec::new();
-    let mut map_block_ids: Vec<String> = Vec::new();
-
-    let options = MatchOptions {
-        case_sensitive: false,
-        require_literal_separator: false,
-        require_literal_leading_dot: false,
+    let StageContent {
+        library, wfc, run, ..
+    } = *content;
+
+    // a boss stage's arena has the exit in it, the boss has to be killed to unlock it
+    let (size, exit_block) = if run.is_boss_stage() {
+        (LayoutSize::for_boss_stage(), library.arena)
+    } else {
+        (LayoutSize::for_stage(run.stage_number), library.exit)
     };
-    for entry in glob_with("map_blocks/*m*", options).unwrap() {
-        match entry {
-            Ok(path) => map_block_ids.push(path.display().to_string()),
-            Err(e) => println!("{}", e),
-        }
-    }
-
-    // doing this in columns not rows
-    for x in -map_size..map_size + 1 {
-        for y in -map_size..map_size + 1 {
-            let map_id = random_map_id(&map_block_ids);
-
-            let mut map_block = MapBlock {
-                x: x - map_size / 2,
-                y: y - map_size / 2,
-                block_id: map_id,
-                // blocks randomly
-                entrance: false,
-                exit: false,
-                tiles: Vec::new(),
-            };
-
-            if x == 0 && y == 0 {
-                map_block.entrance = true;
-            }
-
-            if map_block.x == map_size
-                || map_block.x == -map_size
-                || map_block.y == map_size
-                || map_block.y == -map_size
-            {
-                potential_exits.push(map_block);
-            } else {
-                map_blocks.push(map_block);
-            }
-        }
-    }
-    // grab a random exit and then concatenate both of the vectors of map blocks
-    potential_exits.choose_mut(&mut rng).unwrap().exit = true;
-    map_blocks.append(&mut potential_exits);
-
-    for mut map_block in map_blocks.iter_mut() {
-        if map_block.entrance {
-            map_block.block_id = "map_blocks/entrance.txt".to_string();
-        }
-    }
+    let map_blocks = generate_layout(library, &size, exit_block, wfc, rng).unwrap_or_else(|| {
+        println!(
+            "warning: couldn't generate a layout for stage {}, using the entrance and exit on their own",
+            run.stage_number
+        );
+        // the library only loads if this works
+        fallback_layout(library, exit_block).expect("Couldn't lay out the entrance and exit.")
+    });
 
-    draw_map_blocks(
-        commands,
-        &mut ascii,
-        map_blocks,
-        map_size,
-        &colours,
-        &mut manager_query,
-    );
+    draw_map_blocks(commands, ascii, content, map_blocks, rng);
 }
 
 fn draw_map_blocks(
-    mut commands: &mut Commands,
-    ascii: &mut Res<AsciiSheet>,
+    commands: &mut Commands,
+    ascii: &AsciiSheet,
+    content: &StageContent,
     map_blocks: Vec<MapBlock>,
-    map_size: i32,
-    colours: &Res<ColourScheme>,
-    manager_query: &mut Query<&mut Manager, With<Manager>>,
-    // manager_query: &mut Query<&Manager, With<Manager>>,
+    rng: &mut StdRng,
 ) {
-    let manager = manager_query.single();
-    let mut rng = rand::thread_rng();
-
+    let StageContent {
+        library,
+        enemies,
+        colours,
+        run,
+        ..
+    } = *content;
     let mut tiles = Vec::new();
+    let mut wall_tiles: Vec<IVec2> = Vec::new();
+    let mut borders: Vec<(Vec3, Vec2)> = Vec::new();
 
-    let mut enemies: Vec<Entity> = Vec::new();
-
-    let total_map_size = ((((map_size as f32) * 2.0) + 1.0) * MAP_BLOCK_X) * TILE_SIZE;
-
-    let half_block_size = (32 / 2) * TILE_SIZE as i32;
+    let (min_block, max_block) = layout_bounds(&map_blocks);
 
     for map_block in map_blocks {
-        let mut map_file = File::open(map_block.block_id).expect("Map file not found.");
-        if map_block.exit {
-            map_file = File::open("map_blocks/exit.txt").expect("No exit map file found.");
+        // patrol points and open tiles are found first so enemies get a route as they're spawned
+        let mut patrol_points = Vec::new();
+        let mut open_tiles = Vec::new();
+        for (row, line) in map_block.data(library).rows.iter().enumerate() {
+            let y = MAP_BLOCK_TILES - 1 - row as i32;
+            for (x, char) in line.iter().copied().enumerate() {
+                let translation = tile_translation(IVec2::new(
+                    map_block.x * MAP_BLOCK_TILES + x as i32,
+                    map_block.y * MAP_BLOCK_TILES + y,
+                ));
+                if matches!(library.legend.get(char), Some(LegendEntry::PatrolPoint)) {
+                    patrol_points.push(translation);
+                }
+                if !library.legend.is_wall(char) {
+                    open_tiles.push(translation);
+                }
+            }
         }
-        // iterate through all the characters in the map block file
-        for (y, line) in BufReader::new(map_file).lines().enumerate() {
-            if let Ok(line) = line {
-                for (x, char) in line.chars().enumerate() {
-                    // spawn the walls and entities in the map block according the the text file
-                    // that defines it
-
-                    let mut wall_colour = colours.wall_colour;
-                    if map_block.exit {
-                        wall_colour = colours.colour_0;
-                    }
-
-                    if char == '#' {
-                        let tile_translation = Vec3::new(
-                            (MAP_BLOCK_X * 0.98 * map_block.x as f32 + x as f32 * TILE_SIZE * 0.98)
-                                + half_block_size as f32,
-                            (MAP_BLOCK_Y * 0.98 * map_block.y as f32
-                                + (y as f32) * TILE_SIZE * 0.98)
-                                + half_block_size as f32,
-                            1.0,
-                        );
-
-                        let tile = spawn_ascii_sprite(
-                            &mut commands,
-                            &ascii,
-                            0,
-                            wall_colour,
-                            tile_translation,
-                            Vec2::splat(TILE_SIZE),
-                        );
-
-                        commands.entity(tile).insert(TileCollider {
-                            size: Vec2::splat(TILE_SIZE),
-                        });
 
-                        tiles.push(tile);
-                    } else if char == 'E' {
-                        let tile_translation = Vec3::new(
-                            (MAP_BLOCK_X * 0.98 * map_block.x as f32 + x as f32 * TILE_SIZE * 0.98)
-                                + half_block_size as f32,
-                            (MAP_BLOCK_Y * 0.98 * map_block.y as f32
-                                + (y as f32) * TILE_SIZE * 0.98)
-                                + half_block_size as f32,
-                            1.0,
-                        );
-
-                        let tile = spawn_ascii_sprite(
-                            &mut commands,
-                            &ascii,
-                            char as usize,
-                            colours.colour_0,
+        // iterate through all the characters in the map block file, the first line is the top
+        for (row, line) in map_block.data(library).rows.iter().enumerate() {
+            let y = MAP_BLOCK_TILES - 1 - row as i32;
+            for (x, char) in line.iter().copied().enumerate() {
+                let coords = IVec2::new(
+                    map_block.x * MAP_BLOCK_TILES + x as i32,
+                    map_block.y * MAP_BLOCK_TILES + y,
+                );
+                let tile_translation = tile_translation(coords) + Vec3::Z;
+
+                // spawn whatever the legend says this character is
+                let tile_def = match library.legend.get(char) {
+                    Some(LegendEntry::Spawn(tile_def)) => tile_def,
+                    Some(LegendEntry::Enemy(name)) => {
+                        let def = match enemies.get(name) {
+                            Some(def) => def,
+                            None => {
+                                println!(
+                                    "warning: the legend places a {} but there's no enemy with that name",
+                                    name
+                                );
+                                continue;
+                            }
+                        };
+                        if run.stage_number - 1 < def.min_stage
+                            || !roll_spawn(def.spawn_chance, run, rng)
+                        {
+                            continue;
+                        }
+                        spawn_enemy(
+                            commands,
+                            ascii,
+                            def,
+                            def.colour.resolve(colours, map_block.exit),
                             tile_translation,
-                            Vec2::splat(TILE_SIZE),
+                            AiBrain::new(
+                                patrol_route(tile_translation, &patrol_points, &open_tiles, rng),
+                                def.flee_health,
+                            ),
                         );
+                        continue;
+                    }
+                    _ => continue,
+                };
+                if !roll_spawn(tile_def.spawn_chance, run, rng) {
+                    continue;
+                }
 
-                        commands.entity(tile).insert(Exit);
-
-                        tiles.push(tile);
-                    } else if char == '7' {
-                        let mut chance_of_spawn =
-                            (manager.stage_number as f32 * manager.difficulty_coefficient) as f64;
-                        if chance_of_spawn > 1.0 {
-                            chance_of_spawn = 1.0;
+                let tile = spawn_ascii_sprite(
+                    commands,
+                    ascii,
+                    tile_def.glyph,
+                    tile_def.colour.resolve(colours, map_block.exit),
+                    tile_translation,
+                    Vec2::splat(TILE_SIZE),
+                );
+
+                for component in tile_def.components.iter() {
+                    match component {
+                        TileComponent::Wall => {
+                            commands.entity(tile).insert(TileCollider);
+                            tiles.push(tile);
+                            wall_tiles.push(coords);
                         }
-                        if rng.gen_bool(chance_of_spawn) {
-                            let tile_translation = Vec3::new(
-                                (MAP_BLOCK_X * 0.98 * map_block.x as f32
-                                    + x as f32 * TILE_SIZE * 0.98)
-                                    + half_block_size as f32,
-                                (MAP_BLOCK_Y * 0.98 * map_block.y as f32
-                                    + (y as f32) * TILE_SIZE * 0.98)
-                                    + half_block_size as f32,
-                                1.0,
-                            );
-
-                            let enemy_7 = spawn_runner_enemy(
-                                &mut commands,
-                                &ascii,
-                                4,
-                                colours.colour_0,
-                                tile_translation,
-                                Vec2::splat(TILE_SIZE),
-                                1.0,
-                                TILE_SIZE * 24.0,
-                                305.0,
-                                10.0,
-                            );
+                        TileComponent::Exit => {
+                            commands.entity(tile).insert(Exit);
+                            if run.is_boss_stage() {
+                                commands.entity(tile).insert(LockedExit);
+                            }
+                            tiles.push(tile);
+                        }
+                        TileComponent::Ammo => {
+                            commands.entity(tile).insert(Ammo);
+                        }
+                        TileComponent::WeaponPickup(weapon) => {
+                            commands.entity(tile).insert(WeaponPickup {
+                                weapon: weapon.clone(),
+                            });
                         }
-                    } else if char == 'A' {
-                        let tile_translation = Vec3::new(
-                            (MAP_BLOCK_X * 0.98 * map_block.x as f32 + x as f32 * TILE_SIZE * 0.98)
-                                + half_block_size as f32,
-                            (MAP_BLOCK_Y * 0.98 * map_block.y as f32
-                                + (y as f32) * TILE_SIZE * 0.98)
-                                + half_block_size as f32,
-                            1.0,
-                        );
-                        let fuel = spawn_ascii_sprite(
-                            &mut commands,
-                            &ascii,
-                            char as usize,
-                            colours.colour_2,
-                            tile_translation,
-                            Vec2::splat(TILE_SIZE),
-                        );
-
-                        commands.entity(fuel).insert(Ammo);
-                    } else {
                     }
                 }
             }
         }
-    }
-    let map_border_size: f32 = (((map_size as f32) * 2.0) + 1.0) * MAP_BLOCK_X;
-    let map_border_size_in_tiles: i32 = map_border_size as i32 / TILE_SIZE as i32;
-
-    let map_block_adjust = MAP_BLOCK_X * 0.02;
 
-    // top border
-    let mut tile_translation = Vec3::new(0.0, map_border_size / 2.0 * 0.98, 0.0);
-    let mut tile_size = Vec2::new(map_border_size * 0.98, TILE_SIZE);
-    let top_border = spawn_ascii_sprite(
-        &mut commands,
-        &ascii,
-        0,
-        Color::rgb_u8(255, 255, 255),
-        tile_translation,
-        tile_size,
-    );
-    commands
-        .entity(top_border)
-        .insert(TileCollider { size: tile_size })
-        .insert(Name::new("Top Border"));
-
-    // bottom border
-    tile_translation[1] = -tile_translation[1];
-    let bottom_border = spawn_ascii_sprite(
-        &mut commands,
-        &ascii,
-        0,
-        Color::rgb_u8(255, 255, 255),
-        tile_translation,
-        tile_size,
-    );
-    commands
-        .entity(bottom_border)
-        .insert(TileCollider { size: tile_size })
-        .insert(Name::new("Bottom Border"));
-
-    // left border
-    tile_translation = Vec3::new(-map_border_size / 2.0 * 0.98, 0.0, 0.0);
-    tile_size = Vec2::new(TILE_SIZE, map_border_size * 0.98);
-    let left_border = spawn_ascii_sprite(
-        &mut commands,
-        &ascii,
-        0,
-        Color::rgb_u8(255, 255, 255),
-        tile_translation,
-        tile_size,
-    );
-    commands
-        .entity(left_border)
-        .insert(TileCollider { size: tile_size })
-        .insert(Name::new("Left Border"));
+        // wall off open edges that lead nowhere, the tiles just past the edge are covered
+        let first = IVec2::new(map_block.x, map_block.y) * MAP_BLOCK_TILES;
+        let last = first + IVec2::splat(MAP_BLOCK_TILES - 1);
+        let sealed = map_block.sealed;
+        for (sealed, from, to) in [
+            (
+                sealed.up,
+                IVec2::new(first.x, last.y + 1),
+                IVec2::new(last.x, last.y + 1),
+            ),
+            (
+                sealed.down,
+                IVec2::new(first.x, first.y - 1),
+                IVec2::new(last.x, first.y - 1),
+            ),
+            (
+                sealed.left,
+                IVec2::new(first.x - 1, first.y),
+                IVec2::new(first.x - 1, last.y),
+            ),
+            (
+                sealed.right,
+                IVec2::new(last.x + 1, first.y),
+                IVec2::new(last.x + 1, last.y),
+            ),
+        ] {
+            if sealed {
+                borders.push(spawn_border(commands, ascii, "Sealed Edge", from, to));
+            }
+        }
+    }
 
-    // right border
-    tile_translation = Vec3::new(map_border_size / 2.0 * 0.98, 0.0, 0.0);
-    let right_border = spawn_ascii_sprite(
-        &mut commands,
-        &ascii,
-        0,
-        Color::rgb_u8(255, 255, 255),
-        tile_translation,
-        tile_size,
-    );
-    commands
-        .entity(right_border)
-        .insert(TileCollider { size: tile_size })
-        .insert(Name::new("Right Border"));
+    // the border goes round the bounding box of the layout, one tile outside it
+    let min = min_block * MAP_BLOCK_TILES - IVec2::ONE;
+    let max = (max_block + IVec2::ONE) * MAP_BLOCK_TILES;
+    let outer_borders = [
+        ("Top Border", IVec2::new(min.x, max.y), max),
+        ("Bottom Border", min, IVec2::new(max.x, min.y)),
+        ("Left Border", min, IVec2::new(min.x, max.y)),
+        ("Right Border", IVec2::new(max.x, min.y), max),
+    ];
+    let half_tile = Vec2::splat(TILE_SIZE / 2.0);
+    commands.insert_resource(CameraBounds {
+        min: tile_translation(min).truncate() - half_tile,
+        max: tile_translation(max).truncate() + half_tile,
+    });
+    for (name, from, to) in outer_borders {
+        borders.push(spawn_border(commands, ascii, name, from, to));
+    }
 
     let mut map = commands.spawn_bundle(SpatialBundle {
         transform: Transform {
@@ -364,4 +251,46 @@ fn draw_map_blocks(
     map.insert(Name::new("Map"));
 
     map.push_children(&tiles);
+
+    commands.insert_resource(TileGrid::new(&wall_tiles, borders));
+}
+
+// only rolls when it's a chance so always-spawned tiles don't use up the rng
+fn roll_spawn(spawn_chance: SpawnChance, run: &RunState, rng: &mut StdRng) -> bool {
+    match spawn_chance {
+        SpawnChance::Always => true,
+        _ => rng.gen_bool(spawn_chance.probability(run.stage_number, run.difficulty_coefficient)),
+    }
+}
+
+// a wall over every tile from one corner to the other, for the walls that aren't part of a
+// block. returns where it is and how big it is for the tile grid
+fn spawn_border(
+    commands: &mut Commands,
+    ascii: &AsciiSheet,
+    name: &str,
+    from: IVec2,
+    to: IVec2,
+) -> (Vec3, Vec2) {
+    let (translation, size) = tile_strip(from, to);
+    let border = spawn_ascii_sprite(
+        commands,
+        ascii,
+        0,
+        Color::rgb_u8(255, 255, 255),
+        translation,
+        size,
+    );
+    commands
+        .entity(border)
+        .insert(TileCollider)
+        .insert(Name::new(name.to_string()));
+    (translation, size)
+}
+
+// centre and size of a straight line of tiles from one tile to another
+fn tile_strip(from: IVec2, to: IVec2) -> (Vec3, Vec2) {
+    let translation = (tile_translation(from) + tile_translation(to)) / 2.0;
+    let size = (to - from).abs().as_vec2() * TILE_STEP + Vec2::splat(TILE_SIZE);
+    (translation, size)
 }
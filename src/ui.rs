@@ -1,14 +1,25 @@
 use bevy::prelude::*;
 
-use crate::components::{AmmoText, HealthText, Manager, Player};
+use crate::{
+    components::{AmmoText, HealthText, LivingEntity, Player, SeedText, UiText, WeaponText},
+    run::RunState,
+    settings::Settings,
+    weapon::Weapon,
+    GameState,
+};
 
 pub struct UiPlugin;
 
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app.add_startup_system(make_stage_first)
-            .add_system(update_text)
-            .add_system(update_ammo_text);
+            .add_system(scale_ui_text)
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(update_text)
+                    .with_system(update_ammo_text)
+                    .with_system(update_weapon_text),
+            );
     }
 }
 
@@ -16,10 +27,10 @@ fn make_stage_first(mut commands: Commands, assets: Res<AssetServer>) {
     setup_ui(&mut commands, &assets);
 }
 
-pub fn setup_ui(mut commands: &mut Commands, assets: &Res<AssetServer>) {
+pub fn setup_ui(commands: &mut Commands, assets: &Res<AssetServer>) {
     let health_text = make_text_bundle(
-        &mut commands,
-        &assets,
+        commands,
+        assets,
         30.0,
         "Health: ".to_string(),
         Color::GREEN,
@@ -37,8 +48,8 @@ pub fn setup_ui(mut commands: &mut Commands, assets: &Res<AssetServer>) {
     commands.entity(health_text).insert(HealthText);
 
     let ammo_text = make_text_bundle(
-        &mut commands,
-        &assets,
+        commands,
+        assets,
         30.0,
         "Ammo: ".to_string(),
         Color::WHITE,
@@ -54,6 +65,44 @@ pub fn setup_ui(mut commands: &mut Commands, assets: &Res<AssetServer>) {
         },
     );
     commands.entity(ammo_text).insert(AmmoText);
+
+    let weapon_text = make_text_bundle(
+        commands,
+        assets,
+        30.0,
+        "Weapon: ".to_string(),
+        Color::WHITE,
+        Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                bottom: Val::Px(50.0),
+                left: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        },
+    );
+    commands.entity(weapon_text).insert(WeaponText);
+
+    let seed_text = make_text_bundle(
+        commands,
+        assets,
+        20.0,
+        "Seed: ".to_string(),
+        Color::GRAY,
+        Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                bottom: Val::Px(10.0),
+                right: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        },
+    );
+    commands.entity(seed_text).insert(SeedText);
 }
 
 pub fn make_text_bundle(
@@ -73,35 +122,67 @@ pub fn make_text_bundle(
                     font: assets.load("Hack-Regular.ttf"),
                     font_size,
                     color: colour,
-                    ..default()
                 },
             ),
             TextSection::from_style(TextStyle {
                 font: assets.load("Hack-Regular.ttf"),
                 font_size,
                 color: colour,
-                ..default()
             }),
         ])
         .with_style(style),
     );
+    commands.entity(text).insert(UiText { font_size });
     text
 }
 
+// new text is scaled when it appears, everything is rescaled when the setting changes
+fn scale_ui_text(
+    settings: Res<Settings>,
+    mut text_query: Query<(&UiText, &mut Text, ChangeTrackers<UiText>)>,
+) {
+    for (ui_text, mut text, tracker) in text_query.iter_mut() {
+        if !settings.is_changed() && !tracker.is_added() {
+            continue;
+        }
+        for section in text.sections.iter_mut() {
+            section.style.font_size = ui_text.font_size * settings.ui_scale;
+        }
+    }
+}
+
 fn update_text(
     mut health_query: Query<&mut Text, With<HealthText>>,
-    player_query: Query<(&Player), With<Player>>,
+    player_query: Query<&LivingEntity, With<Player>>,
 ) {
-    let mut health_text = health_query.single_mut();
-    let player = player_query.single();
-    health_text.sections[1].value = format!("{}", player.health);
+    let mut health_text = match health_query.get_single_mut() {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    let player = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    health_text.sections[1].value = format!("{}/{}", player.health, player.max_health);
+}
+
+fn update_ammo_text(mut query: Query<&mut Text, With<AmmoText>>, run: Res<RunState>) {
+    let mut ammo_text = match query.get_single_mut() {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    ammo_text.sections[1].value = format!("{}", run.ammo);
 }
 
-fn update_ammo_text(
-    mut query: Query<&mut Text, With<AmmoText>>,
-    mut manager_query: Query<&Manager, With<Manager>>,
+fn update_weapon_text(
+    mut query: Query<&mut Text, With<WeaponText>>,
+    player_query: Query<&Weapon, With<Player>>,
 ) {
-    let mut ammo_text = query.single_mut();
-    let game_manager = manager_query.single_mut();
-    ammo_text.sections[1].value = format!("{}", game_manager.player_ammo);
+    let mut weapon_text = match query.get_single_mut() {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    if let Ok(weapon) = player_query.get_single() {
+        weapon_text.sections[1].value = weapon.name.clone();
+    }
 }
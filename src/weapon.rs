@@ -0,0 +1,361 @@
+use std::collections::HashSet;
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadState, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use rand::{rngs::StdRng, Rng};
+use serde::Deserialize;
+
+use crate::{
+    bullet::BulletPool,
+    components::{Bullet, Player, Team, WeaponPickup},
+    run::RunState,
+    GameState, TILE_SIZE,
+};
+
+pub const STARTING_WEAPON: &str = "Pistol";
+
+pub struct WeaponPlugin;
+
+impl Plugin for WeaponPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<WeaponFile>()
+            .init_asset_loader::<WeaponLoader>()
+            .add_startup_system(load_weapons)
+            .add_system(build_weapon_library)
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(pick_up_weapons));
+    }
+}
+
+// one entry of assets/weapons/all.weapons.ron
+#[derive(Deserialize, Clone)]
+pub struct WeaponDef {
+    pub name: String,
+    pub fire_rate: f32,
+    pub bullet_speed: f32,
+    pub damage: f32,
+    pub ammo_cost: i32,
+    // degrees
+    #[serde(default)]
+    pub spread: f32,
+    #[serde(default = "one")]
+    pub pellets: u32,
+    #[serde(default = "one")]
+    pub burst: u32,
+    #[serde(default)]
+    pub burst_interval: f32,
+    #[serde(default)]
+    pub piercing: u32,
+    #[serde(default)]
+    pub bounces: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "5d6f0b8e-2f8c-4c4e-9a55-6f1d3c0a7b21"]
+#[serde(transparent)]
+pub struct WeaponFile(Vec<WeaponDef>);
+
+impl WeaponFile {
+    // catches the numbers that would parse but break the game, like a weapon that never fires
+    pub fn validate(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for weapon in self.0.iter() {
+            let fail = |message: &str| Err(format!("weapon \"{}\" {}", weapon.name, message));
+            if weapon.name.is_empty() {
+                return Err("a weapon has no name".to_string());
+            }
+            if !names.insert(weapon.name.as_str()) {
+                return fail("is defined more than once");
+            }
+            if weapon.fire_rate <= 0.0 || weapon.bullet_speed <= 0.0 || weapon.damage <= 0.0 {
+                return fail("needs more than 0 fire_rate, bullet_speed and damage");
+            }
+            if weapon.ammo_cost < 0 {
+                return fail("has a negative ammo_cost");
+            }
+            if !(0.0..=360.0).contains(&weapon.spread) {
+                return fail("has a spread outside 0 to 360 degrees");
+            }
+            if weapon.pellets == 0 || weapon.burst == 0 {
+                return fail("needs at least 1 pellet and 1 volley a burst");
+            }
+            if weapon.burst_interval < 0.0 {
+                return fail("has a negative burst_interval");
+            }
+        }
+        // every run starts out holding it
+        if !names.contains(STARTING_WEAPON) {
+            return Err(format!("there's no \"{}\" to start with", STARTING_WEAPON));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct WeaponLoader;
+
+impl AssetLoader for WeaponLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let weapons: WeaponFile = ron::de::from_bytes(bytes)?;
+            weapons.validate().map_err(bevy::asset::Error::msg)?;
+            load_context.set_default_asset(LoadedAsset::new(weapons));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["weapons.ron"]
+    }
+}
+
+struct WeaponFileHandle(Handle<WeaponFile>);
+
+// every weapon by name, made once the file has loaded and again whenever it's edited
+pub struct WeaponLibrary {
+    weapons: Vec<WeaponDef>,
+}
+
+impl WeaponLibrary {
+    pub fn get(&self, name: &str) -> Option<&WeaponDef> {
+        self.weapons.iter().find(|weapon| weapon.name == name)
+    }
+}
+
+fn load_weapons(mut commands: Commands, assets: Res<AssetServer>) {
+    commands.insert_resource(WeaponFileHandle(assets.load("weapons/all.weapons.ron")));
+}
+
+fn build_weapon_library(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    handle: Res<WeaponFileHandle>,
+    weapon_files: Res<Assets<WeaponFile>>,
+    mut weapon_events: EventReader<AssetEvent<WeaponFile>>,
+    library: Option<Res<WeaponLibrary>>,
+) {
+    let modified = weapon_events.iter().count() > 0;
+    if library.is_some() && !modified {
+        return;
+    }
+    match assets.get_load_state(&handle.0) {
+        LoadState::Loaded => {}
+        // the reason has already been logged by the asset server, an edit that doesn't parse
+        // or validate keeps the weapons there were before
+        LoadState::Failed if library.is_none() => panic!("Couldn't load the weapons."),
+        _ => return,
+    }
+    if let Some(weapon_file) = weapon_files.get(&handle.0) {
+        commands.insert_resource(WeaponLibrary {
+            weapons: weapon_file.0.clone(),
+        });
+    }
+}
+
+// what the player is holding. the stats are looked up by name every shot so editing the
+// weapons file changes them straight away
+#[derive(Component)]
+pub struct Weapon {
+    pub name: String,
+    // seconds until the trigger can be pulled again
+    cooldown: f32,
+    // volleys of the current burst still to come, and the time until the next one
+    burst_left: u32,
+    burst_cooldown: f32,
+}
+
+impl Weapon {
+    pub fn new(name: &str) -> Weapon {
+        Weapon {
+            name: name.to_string(),
+            cooldown: 0.0,
+            burst_left: 0,
+            burst_cooldown: 0.0,
+        }
+    }
+
+    pub fn tick(&mut self, delta_seconds: f32) {
+        self.cooldown = (self.cooldown - delta_seconds).max(0.0);
+        self.burst_cooldown = (self.burst_cooldown - delta_seconds).max(0.0);
+    }
+
+    // true when a volley should be fired this frame. pulling the trigger takes the ammo for
+    // the whole burst up front
+    pub fn trigger(&mut self, weapon: &WeaponDef, held: bool, ammo: &mut i32) -> bool {
+        if self.burst_left > 0 {
+            if self.burst_cooldown > 0.0 {
+                return false;
+            }
+            self.burst_left -= 1;
+            self.burst_cooldown = weapon.burst_interval;
+            return true;
+        }
+        if !held || self.cooldown > 0.0 || *ammo < weapon.ammo_cost {
+            return false;
+        }
+        *ammo -= weapon.ammo_cost;
+        self.cooldown = 1.0 / weapon.fire_rate;
+        self.burst_left = weapon.burst.saturating_sub(1);
+        self.burst_cooldown = weapon.burst_interval;
+        true
+    }
+}
+
+// a single pellet lands anywhere in the spread, several are fanned evenly across it
+pub fn fire_volley(
+    commands: &mut Commands,
+    bullet_pool: &mut BulletPool,
+    weapon: &WeaponDef,
+    position: Vec3,
+    direction: Vec2,
+    rng: &mut StdRng,
+) {
+    let spread = weapon.spread.to_radians();
+    for pellet in 0..weapon.pellets {
+        let angle = if weapon.pellets > 1 {
+            spread * (pellet as f32 / (weapon.pellets - 1) as f32 - 0.5)
+        } else {
+            spread * rng.gen_range(-0.5..=0.5)
+        };
+        let velocity = Vec2::from_angle(angle).rotate(direction) * weapon.bullet_speed;
+        let bullet = Bullet {
+            damage: weapon.damage,
+            piercing: weapon.piercing,
+            bounces: weapon.bounces,
+            ..Bullet::new(Team::Player, velocity)
+        };
+        bullet_pool.fire(commands, position, bullet);
+    }
+}
+
+// walking over a pickup swaps weapons, one for the weapon already held is left alone
+fn pick_up_weapons(
+    mut commands: Commands,
+    mut player_query: Query<(&Transform, &mut Weapon), With<Player>>,
+    pickup_query: Query<(Entity, &Transform, &WeaponPickup), Without<Player>>,
+    mut run: ResMut<RunState>,
+    library: Option<Res<WeaponLibrary>>,
+) {
+    let (player_transform, mut weapon) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let library = match library {
+        Some(library) => library,
+        None => return,
+    };
+    for (pickup, pickup_transform, weapon_pickup) in pickup_query.iter() {
+        if weapon_pickup.weapon == weapon.name
+            || Vec3::distance(player_transform.translation, pickup_transform.translation)
+                >= TILE_SIZE * 0.98
+        {
+            continue;
+        }
+        if library.get(&weapon_pickup.weapon).is_none() {
+            println!(
+                "warning: there's a pickup for {} but no weapon with that name",
+                weapon_pickup.weapon
+            );
+            commands.entity(pickup).despawn();
+            continue;
+        }
+        *weapon = Weapon::new(&weapon_pickup.weapon);
+        run.weapon = weapon_pickup.weapon.clone();
+        commands.entity(pickup).despawn();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pistol() -> WeaponDef {
+        WeaponDef {
+            name: STARTING_WEAPON.to_string(),
+            fire_rate: 1.0,
+            bullet_speed: 600.0,
+            damage: 1.0,
+            ammo_cost: 1,
+            spread: 0.0,
+            pellets: 1,
+            burst: 1,
+            burst_interval: 0.0,
+            piercing: 0,
+            bounces: 0,
+        }
+    }
+
+    fn rejected(weapon: WeaponDef) -> bool {
+        WeaponFile(vec![
+            pistol(),
+            WeaponDef {
+                name: "Broken".to_string(),
+                ..weapon
+            },
+        ])
+        .validate()
+        .is_err()
+    }
+
+    #[test]
+    fn weapons_file_is_valid() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/weapons/all.weapons.ron"
+        );
+        let contents = std::fs::read_to_string(path).expect("Couldn't read the weapons.");
+        let weapons: WeaponFile = ron::from_str(&contents).unwrap();
+        assert_eq!(weapons.validate(), Ok(()));
+    }
+
+    #[test]
+    fn bad_numbers_are_rejected() {
+        assert!(!rejected(pistol()));
+        assert!(rejected(WeaponDef {
+            fire_rate: 0.0,
+            ..pistol()
+        }));
+        assert!(rejected(WeaponDef {
+            pellets: 0,
+            ..pistol()
+        }));
+        assert!(rejected(WeaponDef {
+            burst: 0,
+            ..pistol()
+        }));
+        assert!(rejected(WeaponDef {
+            burst_interval: -0.1,
+            ..pistol()
+        }));
+        assert!(rejected(WeaponDef {
+            spread: 400.0,
+            ..pistol()
+        }));
+        assert!(rejected(WeaponDef {
+            ammo_cost: -1,
+            ..pistol()
+        }));
+    }
+
+    #[test]
+    fn names_are_checked() {
+        assert!(WeaponFile(vec![pistol(), pistol()]).validate().is_err());
+        let other = WeaponDef {
+            name: "Shotgun".to_string(),
+            ..pistol()
+        };
+        let message = WeaponFile(vec![other]).validate().unwrap_err();
+        assert!(message.contains(STARTING_WEAPON));
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use rand::{rngs::StdRng, Rng};
+
+use crate::{
+    legend::Legend,
+    mapblock::{MapBlockData, Openings, MAP_BLOCK_TILES},
+};
+
+// patterns are PATTERN_SIZE x PATTERN_SIZE squares of tiles cut out of the sample blocks
+const PATTERN_SIZE: i32 = 3;
+// number of pattern positions across a block, the last pattern covers the last tiles
+const WAVE_SIZE: i32 = MAP_BLOCK_TILES - PATTERN_SIZE + 1;
+// tiles in the middle of an open edge that are always floor, so blocks line up with neighbours
+const DOOR_START: i32 = 13;
+const DOOR_END: i32 = 18;
+// how many times a block is tried before the layout gives up on it
+const MAX_BLOCK_ATTEMPTS: usize = 10;
+
+// x, row offsets to the right, left, below and above, the order matters for opposite()
+const DIRECTIONS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+pub struct WfcPlugin;
+
+impl Plugin for WfcPlugin {
+    fn build(&self, app: &mut App) {
+        let enabled = std::env::args().any(|arg| arg == "--wfc");
+        if enabled {
+            println!("generating map blocks with wave function collapse");
+        }
+        app.insert_resource(WfcSettings { enabled });
+    }
+}
+
+// pass --wfc to fill stages with generated blocks instead of the ones in assets/map_blocks
+pub struct WfcSettings {
+    pub enabled: bool,
+}
+
+// the overlapping model: every pattern in the samples, how often it turns up and which
+// patterns can sit next to it
+pub struct WfcModel {
+    patterns: Vec<Vec<char>>,
+    weights: Vec<f64>,
+    // propagator[direction][pattern] lists the patterns that can be one step that way from it
+    propagator: [Vec<Vec<usize>>; 4],
+}
+
+impl WfcModel {
+    pub fn new<'a>(samples: impl Iterator<Item = &'a MapBlockData>) -> WfcModel {
+        let mut patterns: Vec<Vec<char>> = Vec::new();
+        let mut weights: Vec<f64> = Vec::new();
+        // pattern -> index, the Vec keeps the order they were found in so seeds replay
+        let mut indices: HashMap<Vec<char>, usize> = HashMap::new();
+        for sample in samples {
+            for row in 0..WAVE_SIZE {
+                for x in 0..WAVE_SIZE {
+                    let pattern: Vec<char> = (0..PATTERN_SIZE * PATTERN_SIZE)
+                        .map(|i| sample.tile(x + i % PATTERN_SIZE, row + i / PATTERN_SIZE))
+                        .collect();
+                    match indices.get(&pattern) {
+                        Some(index) => weights[*index] += 1.0,
+                        None => {
+                            indices.insert(pattern.clone(), patterns.len());
+                            patterns.push(pattern);
+                            weights.push(1.0);
+                        }
+                    }
+                }
+            }
+        }
+
+        let propagator = DIRECTIONS.map(|(dx, dy)| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    (0..patterns.len())
+                        .filter(|other| overlaps(pattern, &patterns[*other], dx, dy))
+                        .collect()
+                })
+                .collect()
+        });
+
+        WfcModel {
+            patterns,
+            weights,
+            propagator,
+        }
+    }
+
+    // a new block with these edges open, where every open edge has floor at its middle and
+    // every closed edge is solid wall. the open edges are always joined up inside the block
+    pub fn generate_block(
+        &self,
+        name: String,
+        legend: &Legend,
+        openings: Openings,
+        rng: &mut StdRng,
+    ) -> Option<MapBlockData> {
+        let constraints = edge_constraints(openings);
+        for _ in 0..MAX_BLOCK_ATTEMPTS {
+            if let Some(rows) = Wave::new(self).run(&constraints, rng) {
+                if doors_connected(&rows, legend, openings) {
+                    return Some(MapBlockData::from_rows(name, rows, openings));
+                }
+            }
+        }
+        None
+    }
+}
+
+// do two patterns agree on the tiles they share when b is dx, dy from a
+fn overlaps(a: &[char], b: &[char], dx: i32, dy: i32) -> bool {
+    let from_x = dx.max(0);
+    let to_x = (PATTERN_SIZE + dx).min(PATTERN_SIZE);
+    let from_y = dy.max(0);
+    let to_y = (PATTERN_SIZE + dy).min(PATTERN_SIZE);
+    (from_y..to_y).all(|y| {
+        (from_x..to_x).all(|x| {
+            a[(x + y * PATTERN_SIZE) as usize] == b[(x - dx + (y - dy) * PATTERN_SIZE) as usize]
+        })
+    })
+}
+
+fn opposite(direction: usize) -> usize {
+    direction ^ 1
+}
+
+// tiles that have to be a certain character, as (x, row, char)
+fn edge_constraints(openings: Openings) -> Vec<(i32, i32, char)> {
+    let last = MAP_BLOCK_TILES - 1;
+    let mut constraints = Vec::new();
+    for i in 0..MAP_BLOCK_TILES {
+        let door = (DOOR_START..=DOOR_END).contains(&i);
+        let edges = [
+            (openings.up, i, 0),
+            (openings.down, i, last),
+            (openings.left, 0, i),
+            (openings.right, last, i),
+        ];
+        for (open, x, row) in edges {
+            if !open {
+                constraints.push((x, row, '#'));
+            } else if door {
+                constraints.push((x, row, '.'));
+            }
+        }
+    }
+    constraints
+}
+
+// flood fills from the first door and checks every other door was reached
+fn doors_connected(rows: &[Vec<char>], legend: &Legend, openings: Openings) -> bool {
+    let last = MAP_BLOCK_TILES - 1;
+    let door = (DOOR_START + DOOR_END) / 2;
+    let doors: Vec<(i32, i32)> = [
+        (openings.up, (door, 0)),
+        (openings.down, (door, last)),
+        (openings.left, (0, door)),
+        (openings.right, (last, door)),
+    ]
+    .into_iter()
+    .filter(|(open, _)| *open)
+    .map(|(_, tile)| tile)
+    .collect();
+    let start = match doors.first() {
+        Some(start) => *start,
+        None => return true,
+    };
+
+    let size = MAP_BLOCK_TILES as usize;
+    let mut visited = vec![false; size * size];
+    visited[start.0 as usize + start.1 as usize * size] = true;
+    let mut stack = vec![start];
+    while let Some((x, row)) = stack.pop() {
+        for (dx, dy) in DIRECTIONS {
+            let (x, row) = (x + dx, row + dy);
+            if x < 0 || row < 0 || x > last || row > last {
+                continue;
+            }
+            let index = x as usize + row as usize * size;
+            if !visited[index] && !legend.is_wall(rows[row as usize][x as usize]) {
+                visited[index] = true;
+                stack.push((x, row));
+            }
+        }
+    }
+    doors
+        .iter()
+        .all(|(x, row)| visited[*x as usize + *row as usize * size])
+}
+
+// the patterns each position of a block could still be
+struct Wave<'a> {
+    model: &'a WfcModel,
+    possible: Vec<bool>,
+    // compatible[(cell * patterns + pattern) * 4 + direction] counts the patterns in the
+    // neighbour the other way from direction that still allow pattern here
+    compatible: Vec<usize>,
+    remaining: Vec<usize>,
+    weight_sums: Vec<f64>,
+    weight_log_sums: Vec<f64>,
+    to_propagate: Vec<(usize, usize)>,
+}
+
+impl<'a> Wave<'a> {
+    fn new(model: &'a WfcModel) -> Wave<'a> {
+        let cells = (WAVE_SIZE * WAVE_SIZE) as usize;
+        let patterns = model.patterns.len();
+        let mut compatible = Vec::with_capacity(cells * patterns * 4);
+        for _ in 0..cells {
+            for pattern in 0..patterns {
+                for direction in 0..4 {
+                    compatible.push(model.propagator[opposite(direction)][pattern].len());
+                }
+            }
+        }
+        let weight_sum: f64 = model.weights.iter().sum();
+        let weight_log_sum: f64 = model.weights.iter().map(|w| w * w.ln()).sum();
+        Wave {
+            model,
+            possible: vec![true; cells * patterns],
+            compatible,
+            remaining: vec![patterns; cells],
+            weight_sums: vec![weight_sum; cells],
+            weight_log_sums: vec![weight_log_sum; cells],
+            to_propagate: Vec::new(),
+        }
+    }
+
+    fn run(mut self, constraints: &[(i32, i32, char)], rng: &mut StdRng) -> Option<Vec<Vec<char>>> {
+        for (x, row, char) in constraints.iter().copied() {
+            self.constrain(x, row, char);
+        }
+        self.propagate()?;
+
+        while let Some(cell) = self.lowest_entropy(rng)? {
+            self.observe(cell, rng);
+            self.propagate()?;
+        }
+        Some(self.rows())
+    }
+
+    // rules out every pattern that would put something other than char at x, row
+    fn constrain(&mut self, x: i32, row: i32, char: char) {
+        let patterns = self.model.patterns.len();
+        for wave_y in (row - PATTERN_SIZE + 1).max(0)..=row.min(WAVE_SIZE - 1) {
+            for wave_x in (x - PATTERN_SIZE + 1).max(0)..=x.min(WAVE_SIZE - 1) {
+                let cell = (wave_x + wave_y * WAVE_SIZE) as usize;
+                let offset = (x - wave_x + (row - wave_y) * PATTERN_SIZE) as usize;
+                for pattern in 0..patterns {
+                    if self.possible[cell * patterns + pattern]
+                        && self.model.patterns[pattern][offset] != char
+                    {
+                        self.ban(cell, pattern);
+                    }
+                }
+            }
+        }
+    }
+
+    fn ban(&mut self, cell: usize, pattern: usize) {
+        let patterns = self.model.patterns.len();
+        self.possible[cell * patterns + pattern] = false;
+        for direction in 0..4 {
+            self.compatible[(cell * patterns + pattern) * 4 + direction] = 0;
+        }
+        let weight = self.model.weights[pattern];
+        self.remaining[cell] -= 1;
+        self.weight_sums[cell] -= weight;
+        self.weight_log_sums[cell] -= weight * weight.ln();
+        self.to_propagate.push((cell, pattern));
+    }
+
+    // None when a position has run out of patterns
+    fn propagate(&mut self) -> Option<()> {
+        let patterns = self.model.patterns.len();
+        while let Some((cell, pattern)) = self.to_propagate.pop() {
+            if self.remaining[cell] == 0 {
+                return None;
+            }
+            let x = cell as i32 % WAVE_SIZE;
+            let y = cell as i32 / WAVE_SIZE;
+            for (direction, (dx, dy)) in DIRECTIONS.iter().enumerate() {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= WAVE_SIZE || ny >= WAVE_SIZE {
+                    continue;
+                }
+                let neighbour = (nx + ny * WAVE_SIZE) as usize;
+                for other in self.model.propagator[direction][pattern].iter().copied() {
+                    let index = (neighbour * patterns + other) * 4 + direction;
+                    if self.compatible[index] == 0 {
+                        continue;
+                    }
+                    self.compatible[index] -= 1;
+                    if self.compatible[index] == 0 {
+                        self.ban(neighbour, other);
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+
+    // Some(None) once every position is decided, None if one has no patterns left
+    fn lowest_entropy(&self, rng: &mut StdRng) -> Option<Option<usize>> {
+        let mut lowest = None;
+        let mut lowest_entropy = f64::MAX;
+        for (cell, remaining) in self.remaining.iter().enumerate() {
+            match remaining {
+                0 => return None,
+                1 => continue,
+                _ => {}
+            }
+            let sum = self.weight_sums[cell];
+            // a little noise so ties don't always go to the top left
+            let entropy = sum.ln() - self.weight_log_sums[cell] / sum + rng.gen::<f64>() * 1e-6;
+            if entropy < lowest_entropy {
+                lowest_entropy = entropy;
+                lowest = Some(cell);
+            }
+        }
+        Some(lowest)
+    }
+
+    // picks one of the patterns still possible here, weighted by how common it was
+    fn observe(&mut self, cell: usize, rng: &mut StdRng) {
+        let patterns = self.model.patterns.len();
+        let mut roll = rng.gen::<f64>() * self.weight_sums[cell];
+        let mut chosen = None;
+        for pattern in 0..patterns {
+            if self.possible[cell * patterns + pattern] {
+                chosen = Some(pattern);
+                roll -= self.model.weights[pattern];
+                if roll <= 0.0 {
+                    break;
+                }
+            }
+        }
+        let chosen = chosen.unwrap();
+        for pattern in 0..patterns {
+            if pattern != chosen && self.possible[cell * patterns + pattern] {
+                self.ban(cell, pattern);
+            }
+        }
+    }
+
+    fn rows(&self) -> Vec<Vec<char>> {
+        let patterns = self.model.patterns.len();
+        (0..MAP_BLOCK_TILES)
+            .map(|row| {
+                (0..MAP_BLOCK_TILES)
+                    .map(|x| {
+                        let wave_x = x.min(WAVE_SIZE - 1);
+                        let wave_y = row.min(WAVE_SIZE - 1);
+                        let cell = (wave_x + wave_y * WAVE_SIZE) as usize;
+                        let pattern = (0..patterns)
+                            .find(|pattern| self.possible[cell * patterns + pattern])
+                            .unwrap();
+                        let offset = x - wave_x + (row - wave_y) * PATTERN_SIZE;
+                        self.model.patterns[pattern][offset as usize]
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::mapblock::{test_library, MapBlockLibrary};
+
+    fn model(library: &MapBlockLibrary) -> WfcModel {
+        WfcModel::new(library.fillers().map(|block| &library.blocks[block]))
+    }
+
+    fn generate(
+        model: &WfcModel,
+        library: &MapBlockLibrary,
+        flags: &str,
+        seed: u64,
+    ) -> Option<MapBlockData> {
+        let openings = Openings::from_flags(flags).unwrap();
+        let mut rng = StdRng::seed_from_u64(seed);
+        model.generate_block(flags.to_string(), &library.legend, openings, &mut rng)
+    }
+
+    #[test]
+    fn same_seed_same_block() {
+        let library = test_library();
+        let model = model(&library);
+        let first = generate(&model, &library, "udlr", 7).unwrap();
+        let second = generate(&model, &library, "udlr", 7).unwrap();
+        assert_eq!(first.rows, second.rows);
+    }
+
+    // closed edges are forced to wall, which the samples have to be able to fit around
+    #[test]
+    fn typical_openings_generate() {
+        let library = test_library();
+        let model = model(&library);
+        for flags in ["u", "ud", "lr", "dl", "udr", "udlr"] {
+            for seed in 0..2 {
+                let openings = Openings::from_flags(flags).unwrap();
+                let block = generate(&model, &library, flags, seed)
+                    .unwrap_or_else(|| panic!("{} with seed {} didn't generate", flags, seed));
+                assert_eq!(block.openings(), openings);
+                assert!(doors_connected(&block.rows, &library.legend, openings));
+                for (x, row, char) in edge_constraints(openings) {
+                    assert_eq!(block.rows[row as usize][x as usize], char);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn walled_off_doors_arent_connected() {
+        let library = test_library();
+        let size = MAP_BLOCK_TILES as usize;
+        let mut rows = vec![vec!['.'; size]; size];
+        let openings = Openings::from_flags("ud").unwrap();
+        assert!(doors_connected(&rows, &library.legend, openings));
+        rows[size / 2] = vec!['#'; size];
+        assert!(!doors_connected(&rows, &library.legend, openings));
+        // the left door is above the wall, on the same side as the top one
+        let openings = Openings::from_flags("ul").unwrap();
+        assert!(doors_connected(&rows, &library.legend, openings));
+    }
+}